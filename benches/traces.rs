@@ -0,0 +1,265 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    hint::black_box,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use simple_text_crdt::{
+    allocation::Allocation,
+    logoot::Logoot,
+    rga::Rga,
+    sequence::SequenceCrdt,
+    trace::{Patch, Trace},
+    treedoc::Treedoc,
+};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+use common::Rng;
+
+/*
+Replays synthetic editing traces through every `SequenceCrdt` backend:
+`cargo bench`, or `cargo bench -- typing treedoc` to run only the rows
+matching all the given words. Trace files given as `.json` arguments, e.g. a
+decompressed automerge-paper.json, run along with them.
+
+Local is generating an op plus applying it, remote is applying the same ops on
+a fresh replica. Heap is what the document holds once the trace is done,
+measured by counting allocations.
+*/
+
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOC: Counting = Counting;
+
+const OPS: usize = 10_000;
+
+type Generator = (&'static str, fn() -> Vec<Patch>);
+
+// Typing at a cursor that mostly moves forwards, fixing typos as it goes
+fn typing() -> Vec<Patch> {
+    let mut rng = Rng(1);
+    let (mut len, mut cursor, mut patches) = (0, 0, Vec::new());
+    while patches.len() < OPS {
+        if rng.below(50) == 0 {
+            cursor = rng.below(len + 1);
+        }
+        if cursor > 0 && rng.below(10) == 0 {
+            cursor -= 1;
+            patches.push(Patch {
+                pos: cursor,
+                del: 1,
+                text: String::new(),
+            });
+            len -= 1;
+        } else {
+            patches.push(Patch {
+                pos: cursor,
+                del: 0,
+                text: rng.text(1),
+            });
+            cursor += 1;
+            len += 1;
+        }
+    }
+    patches
+}
+
+// Typing at the end only, like a log being written
+fn appending() -> Vec<Patch> {
+    let mut rng = Rng(5);
+    (0..OPS)
+        .map(|pos| Patch {
+            pos,
+            del: 0,
+            text: rng.text(1),
+        })
+        .collect()
+}
+
+fn random_inserts() -> Vec<Patch> {
+    let mut rng = Rng(2);
+    (0..OPS)
+        .map(|len| Patch {
+            pos: rng.below(len + 1),
+            del: 0,
+            text: rng.text(1),
+        })
+        .collect()
+}
+
+// Whole paragraphs pasted at random spots, typing in between
+fn paste_bursts() -> Vec<Patch> {
+    let mut rng = Rng(3);
+    let (mut len, mut ops, mut patches) = (0, 0, Vec::new());
+    while ops < OPS {
+        let text = if rng.below(20) == 0 {
+            rng.text(200)
+        } else {
+            rng.text(1)
+        };
+        ops += text.len();
+        len += text.len();
+        patches.push(Patch {
+            pos: rng.below(len - text.len() + 1),
+            del: 0,
+            text,
+        });
+    }
+    patches
+}
+
+// Types a document, then deletes most of it again a few chars at a time
+fn heavy_deletion() -> Vec<Patch> {
+    let mut rng = Rng(4);
+    let mut patches: Vec<_> = (0..OPS / 2)
+        .map(|len| Patch {
+            pos: len,
+            del: 0,
+            text: rng.text(1),
+        })
+        .collect();
+    let mut len = OPS / 2;
+    while patches.len() < OPS && len > OPS / 10 {
+        let del = 1 + rng.below(3);
+        patches.push(Patch {
+            pos: rng.below(len - del + 1),
+            del,
+            text: String::new(),
+        });
+        len -= del;
+    }
+    patches
+}
+
+struct Run {
+    ops: usize,
+    local: Duration,
+    remote: Duration,
+    heap: usize,
+}
+
+// Replays on `doc`, then on a fresh replica of site 2
+fn replay<S: SequenceCrdt>(trace: &Trace, mut doc: S) -> (S, Run) {
+    let start = Instant::now();
+    let ops = trace.replay(&mut doc).unwrap();
+    let local = start.elapsed();
+
+    // The op log already exists here, so the heap growth is the replica alone
+    let heap = LIVE.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut remote = S::with_site(2);
+    for op in &ops {
+        remote.apply(black_box(op.clone())).unwrap();
+    }
+    let remote_time = start.elapsed();
+    let heap = LIVE.load(Ordering::Relaxed) - heap;
+    assert_eq!(remote.text(), doc.text());
+
+    let run = Run {
+        ops: ops.len(),
+        local: local / ops.len().max(1) as u32,
+        remote: remote_time / ops.len().max(1) as u32,
+        heap,
+    };
+    (doc, run)
+}
+
+// Records the text the patches end in, so replaying checks it
+fn synthetic(patches: Vec<Patch>) -> Trace {
+    let mut text = Vec::new();
+    for patch in &patches {
+        text.splice(patch.pos..patch.pos + patch.del, patch.text.chars());
+    }
+    Trace {
+        start: String::new(),
+        patches,
+        end: Some(text.into_iter().collect()),
+    }
+}
+
+fn main() {
+    let (files, filters): (Vec<String>, Vec<String>) = env::args()
+        .skip(1)
+        .filter(|a| !a.starts_with("--"))
+        .partition(|a| a.ends_with(".json"));
+    let selected = |row: &str| filters.iter().all(|f| row.contains(f.as_str()));
+    let generators: [Generator; 5] = [
+        ("typing", typing),
+        ("appending", appending),
+        ("random-inserts", random_inserts),
+        ("paste-bursts", paste_bursts),
+        ("heavy-deletion", heavy_deletion),
+    ];
+    let mut traces: Vec<(String, Trace)> = generators
+        .iter()
+        .map(|(name, generate)| (name.to_string(), synthetic(generate())))
+        .collect();
+    for file in files {
+        let trace = Trace::load(&file).unwrap_or_else(|e| panic!("{file}: {e}"));
+        let name = Path::new(&file).file_stem().unwrap().to_string_lossy();
+        traces.push((name.into_owned(), trace));
+    }
+
+    println!(
+        "{:<16} {:<16} {:>6} {:>10} {:>10} {:>10} {:>9} {:>6}",
+        "trace", "backend", "ops", "local/op", "remote/op", "heap KiB", "avg PosID", "depth"
+    );
+    for (name, trace) in &traces {
+        let row = |backend: &str, run: Run, shape: Option<(f64, usize)>| {
+            let (avg, depth) = match shape {
+                Some((avg, depth)) => (format!("{avg:.1}"), depth.to_string()),
+                None => ("-".to_string(), "-".to_string()),
+            };
+            println!(
+                "{:<16} {:<16} {:>6} {:>10?} {:>10?} {:>10} {:>9} {:>6}",
+                name,
+                backend,
+                run.ops,
+                run.local,
+                run.remote,
+                run.heap / 1024,
+                avg,
+                depth
+            );
+        };
+        for (backend, allocation) in [
+            ("treedoc", Allocation::Simple),
+            ("treedoc/balanced", Allocation::Balanced),
+        ] {
+            if selected(&format!("{name} {backend}")) {
+                let doc = Treedoc {
+                    allocation,
+                    ..Treedoc::default()
+                };
+                let (doc, run) = replay(trace, doc);
+                let stats = doc.stats();
+                row(backend, run, Some((stats.avg_pos_id_len, stats.depth)));
+            }
+        }
+        if selected(&format!("{name} rga")) {
+            row("rga", replay(trace, Rga::with_site(1)).1, None);
+        }
+        if selected(&format!("{name} logoot")) {
+            row("logoot", replay(trace, Logoot::with_site(1)).1, None);
+        }
+    }
+}
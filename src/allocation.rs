@@ -0,0 +1,80 @@
+use crate::{
+    node::{AtPosition, Node},
+    pos_id::{PathComponent, PosID},
+    treedoc::Treedoc,
+};
+
+/*
+Where a Treedoc puts the atoms typed at the end of a subtree. `Simple` hangs
+each one off the right of the previous atom, so typing a document of n chars
+makes a path of length n. `Balanced` fills complete binary trees in infix
+order instead: the spine of right links from the root carries left subtrees of
+growing height, and the empty major nodes a subtree needs are created ahead of
+the atoms that fill them ("pre-grown"). PosIDs then grow with about 2·log2(n).
+
+Only appends are affected, inserts between two atoms go where they always did.
+Replicas may use different strategies, every PosID is understood by all.
+*/
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Allocation {
+    #[default]
+    Simple,
+    Balanced,
+}
+
+impl Treedoc {
+    // PosID for an atom typed right after the last mini of the major node `q` at
+    // `q_path`, which has no right subtree. Also returns how many empty major
+    // nodes above the new one the insert has to create.
+    pub(crate) fn balanced_append(&self, q_path: &[PathComponent], q: &Node) -> (PosID, usize) {
+        // Subtrees are complete, so the leftmost branch gives the height
+        let mut height = 0;
+        let mut node = q.left.clone();
+        while let Some(n) = node {
+            height += 1;
+            node = n.borrow().left.clone();
+        }
+
+        // Up the right links of major nodes; one left link up from there is the
+        // node right after `q`. While that one is still empty `q` is inside a
+        // subtree being filled, otherwise it is on a spine: at the root, below
+        // a mini or where typing started in the middle of the text.
+        let from_major = |i: usize| i == 0 || q_path[i - 1].1.is_none();
+        let mut end = q_path.len();
+        while end > 0 && q_path[end - 1] == PathComponent(1, None) && from_major(end - 1) {
+            end -= 1;
+        }
+        let empty_after = end > 0
+            && q_path[end - 1] == PathComponent(0, None)
+            && from_major(end - 1)
+            && matches!(
+                Self::traverse_node_at_pos_id(
+                    AtPosition::Major(self.root.clone()),
+                    &q_path[..end - 1].to_vec(),
+                ),
+                AtPosition::Major(Some(after)) if after.borrow().children.borrow().is_empty()
+            );
+
+        let grow = match (empty_after, height) {
+            (true, 0) => {
+                // Fill the pre-grown node
+                let mut pos_id = PosID(q_path[..end - 1].to_vec());
+                pos_id
+                    .0
+                    .push(PathComponent(0, Some(self.unique_disambiguator)));
+                return (pos_id, 0);
+            }
+            // Right subtree as high as the left one
+            (true, _) => height - 1,
+            // Next spine node, one higher than this one
+            (false, _) => height + 1,
+        };
+        let mut pos_id = PosID(q_path.to_vec());
+        pos_id.0.push(PathComponent(1, None));
+        pos_id
+            .0
+            .extend(std::iter::repeat_n(PathComponent(0, None), grow));
+        (pos_id, grow)
+    }
+}
@@ -0,0 +1,30 @@
+use simple_text_crdt::relay::RelayServer;
+use std::{io::Error, path::PathBuf, time::Duration};
+
+// Usage: relay [addr] [data dir] [websocket addr],
+// e.g. `relay 0.0.0.0:9001 /var/lib/relay 0.0.0.0:9002`
+fn main() -> Result<(), Error> {
+    let mut args = std::env::args().skip(1);
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:9001".to_string());
+    let dir = PathBuf::from(args.next().unwrap_or_else(|| "relay-data".to_string()));
+    let ws_addr = args.next();
+
+    let mut server = RelayServer::bind(addr, Some(dir.clone()))?;
+    println!(
+        "relay listening on {}, rooms in {}",
+        server.local_addr(),
+        dir.display()
+    );
+    if let Some(ws_addr) = ws_addr {
+        #[cfg(feature = "websocket")]
+        println!("websocket clients on {}", server.listen_ws(ws_addr)?);
+        #[cfg(not(feature = "websocket"))]
+        eprintln!("built without websocket support, ignoring {ws_addr}");
+    }
+    loop {
+        // A failing room (e.g. a full disk) should not take the others down
+        if let Err(e) = server.poll_timeout(Duration::from_secs(1)) {
+            eprintln!("error: {e}");
+        }
+    }
+}
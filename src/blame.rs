@@ -0,0 +1,123 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ops::{Bound, RangeBounds, RangeInclusive},
+    rc::Rc,
+};
+
+use crate::{
+    iter::AllMinis,
+    node::{Mininode, SDIS},
+    treedoc::Treedoc,
+};
+
+/*
+Who wrote what. Every mininode keeps the (lamport, site) stamp of the insert
+that made it, and tombstones the one of the delete that removed the atom, so a
+document can be annotated like `git blame` does, deleted text included. A moved
+atom keeps the stamps of its original insert. Of concurrent deletes of an atom
+the earliest gets the blame.
+*/
+
+/// A site and the Lamport timestamps of its first and last edit in a run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribution {
+    pub site: SDIS,
+    pub time: RangeInclusive<u64>,
+}
+
+/// Neighbouring atoms inserted by the same site (and deleted by the same site,
+/// if they are gone)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameRun {
+    pub inserted: Attribution,
+    /// Who deleted the text; None while it is visible
+    pub deleted: Option<Attribution>,
+    pub text: String,
+}
+
+impl Attribution {
+    fn new((lamport, site): (u64, SDIS)) -> Self {
+        Attribution {
+            site,
+            time: lamport..=lamport,
+        }
+    }
+
+    fn extend(&mut self, lamport: u64) {
+        self.time = *self.time.start().min(&lamport)..=*self.time.end().max(&lamport);
+    }
+}
+
+impl Treedoc {
+    /// Runs of the chars in `range` (clamped like `slice`) by who inserted them.
+    /// Deleted text shows up where it was, attributed to who deleted it too. It
+    /// belongs to the range of the visible char right after it (the last char,
+    /// for deleted text at the end).
+    pub fn blame(&self, range: impl RangeBounds<usize>) -> Vec<BlameRun> {
+        let start = match range.start_bound() {
+            Bound::Included(&s) => s,
+            Bound::Excluded(&s) => s + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&e) => e + 1,
+            Bound::Excluded(&e) => e,
+            Bound::Unbounded => self.doc_length,
+        }
+        .min(self.doc_length);
+
+        // Slots of moves take their stamps from the moved atom
+        let origins: HashMap<*const RefCell<Mininode>, Rc<RefCell<Mininode>>> = self
+            .moves
+            .iter()
+            .filter_map(|m| {
+                let slot = self.mini_at(&m.slot.atom_path())?;
+                Some((Rc::as_ptr(&slot), self.mini_at(&m.origin.to_pos_id())?))
+            })
+            .collect();
+
+        let mut runs: Vec<BlameRun> = Vec::new();
+        let mut index = 0;
+        for mini in AllMinis::new(self) {
+            let original = origins.get(&Rc::as_ptr(&mini)).unwrap_or(&mini).borrow();
+            let mini = mini.borrow();
+            if mini.moved {
+                continue;
+            }
+            // Deleted atoms go with the visible char after them, or the last one
+            let owner = if mini.tombstone {
+                index.min(self.doc_length.saturating_sub(1))
+            } else {
+                index += 1;
+                index - 1
+            };
+            if owner >= end && self.doc_length > 0 {
+                break;
+            }
+            if owner < start {
+                continue;
+            }
+            let deleted = original.deleted.first().copied().filter(|_| mini.tombstone);
+            let ch = mini.atom.to_char();
+            match runs.last_mut() {
+                Some(run)
+                    if run.inserted.site == original.inserted.1
+                        && run.deleted.as_ref().map(|d| d.site) == deleted.map(|d| d.1) =>
+                {
+                    run.inserted.extend(original.inserted.0);
+                    if let (Some(run), Some((lamport, _))) = (&mut run.deleted, deleted) {
+                        run.extend(lamport);
+                    }
+                    run.text.push(ch);
+                }
+                _ => runs.push(BlameRun {
+                    inserted: Attribution::new(original.inserted),
+                    deleted: deleted.map(Attribution::new),
+                    text: ch.to_string(),
+                }),
+            }
+        }
+        runs
+    }
+}
@@ -0,0 +1,123 @@
+use std::{cell::RefCell, io::Result, rc::Rc};
+
+use crate::{
+    history::ClockVector,
+    node::{AtPosition, SDIS},
+    pos_id::PathComponent,
+    treedoc::{Signal, Treedoc},
+};
+
+/*
+Branches. `fork` makes an independent copy of a document, e.g. for suggestion
+mode, that shares the whole tree with the original at first. Before a node is
+changed the nodes on the path from the root to it are copied, if another
+document still uses them ("path copying"), so an edit costs a copy of one path
+and everything else stays shared.
+
+A branch is just another replica with its own site. `changes_since` lists the
+edits a document has that a version doesn't include, rebuilt from the stamps in
+the tree (see `history`), so no op log is needed; `merge` applies the ones of
+another document all-or-nothing. Merging both ways converges like delivering
+the same edits to concurrent replicas does.
+*/
+
+// Points `rc` at a node only this document uses, copying it if it is shared.
+// Its children stay shared.
+fn unshare<T: Clone>(rc: &mut Rc<RefCell<T>>) -> Rc<RefCell<T>> {
+    if Rc::strong_count(rc) > 1 {
+        let copy = rc.borrow().clone();
+        *rc = Rc::new(RefCell::new(copy));
+    }
+    rc.clone()
+}
+
+impl Treedoc {
+    /// A copy of the document editing as `site`, which must not be used by any
+    /// other replica. The tree is shared until either side changes it, so this
+    /// is cheap. Subscribers are not copied.
+    pub fn fork(&self, site: SDIS) -> Treedoc {
+        Treedoc {
+            root: self.root.clone(),
+            unique_disambiguator: site,
+            doc_length: self.doc_length,
+            lamport: self.lamport,
+            marks: self.marks.clone(),
+            moves: self.moves.clone(),
+            move_index: self.move_index.clone(),
+            allocation: self.allocation,
+            version: self.version.clone(),
+            checkpoints: self.checkpoints.clone(),
+            ..Treedoc::default()
+        }
+    }
+
+    /// Remembers the current version under `name`, e.g. where a branch was
+    /// forked. Checkpoints are local to this document and its forks.
+    pub fn checkpoint(&mut self, name: impl Into<String>) {
+        self.checkpoints.insert(name.into(), self.version.clone());
+    }
+
+    /// Signals for the edits applied here that `version` does not include, in an
+    /// order a replica at `version` can apply them. Walks the whole tree.
+    pub fn changes_since(&self, version: &ClockVector) -> Vec<Signal> {
+        let mut stamps = Vec::new();
+        self.snapshot()
+            .into_iter()
+            .filter(|sig| {
+                stamps.clear();
+                sig.stamps(&mut stamps);
+                stamps.iter().any(|stamp| !version.includes(*stamp))
+            })
+            .collect()
+    }
+
+    /// Applies the edits of `other` missing here as one transaction
+    pub fn merge(&mut self, other: &Treedoc) -> Result<()> {
+        let changes = other.changes_since(&self.version);
+        if changes.is_empty() {
+            return Ok(());
+        }
+        self.apply(Signal::Transaction(changes))
+    }
+
+    // Copies the nodes on `path` that are shared with a fork, as far as the path
+    // exists, so they can be changed in place
+    pub(crate) fn own_path(&mut self, path: &[PathComponent]) {
+        let Some(root) = self.root.as_mut() else {
+            return;
+        };
+        let mut at = AtPosition::Major(Some(unshare(root)));
+        for comp in path {
+            let next = match (&at, comp) {
+                (AtPosition::Major(Some(node)), PathComponent(0, Some(dis))) => {
+                    let node = node.borrow();
+                    let mut children = node.children.borrow_mut();
+                    let mini = children
+                        .iter_mut()
+                        .find(|mini| mini.borrow().disambiguator == *dis);
+                    AtPosition::Mini(mini.map(unshare))
+                }
+                (AtPosition::Major(Some(node)), PathComponent(dir @ (0 | 1), None)) => {
+                    let mut node = node.borrow_mut();
+                    let child = if *dir == 0 {
+                        &mut node.left
+                    } else {
+                        &mut node.right
+                    };
+                    AtPosition::Major(child.as_mut().map(unshare))
+                }
+                (AtPosition::Mini(Some(mini)), PathComponent(dir @ (0 | 1), None)) => {
+                    let mut mini = mini.borrow_mut();
+                    let child = if *dir == 0 {
+                        &mut mini.left
+                    } else {
+                        &mut mini.right
+                    };
+                    AtPosition::Major(child.as_mut().map(unshare))
+                }
+                _ => return,
+            };
+            at = next;
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::{cell::RefCell, fmt::Write, rc::Rc};
+
+use crate::{
+    node::Node,
+    pos_id::{PathComponent, PosID},
+    treedoc::Treedoc,
+};
+
+/*
+Graphviz export of the internal tree: major nodes are circles labelled with their
+path and visible subtree length, mininodes are boxes with their atom and
+disambiguator (dashed and grey once tombstoned, dotted when moved away).
+Render with `dot -Tsvg doc.dot > doc.svg`.
+*/
+
+struct DotWriter {
+    out: String,
+    next_id: usize,
+}
+
+impl Treedoc {
+    pub fn to_dot(&self) -> String {
+        let mut writer = DotWriter {
+            out: String::from("digraph treedoc {\n  node [fontname=\"monospace\"];\n"),
+            next_id: 0,
+        };
+        writer.major(&self.root, &mut PosID::new());
+        writer.out.push_str("}\n");
+        writer.out
+    }
+}
+
+impl DotWriter {
+    fn new_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("n{}", self.next_id - 1)
+    }
+
+    fn edge(&mut self, from: &str, to: &str, attrs: &str) {
+        writeln!(self.out, "  {from} -> {to} [{attrs}];").unwrap();
+    }
+
+    // Writes the subtree and returns the id of its top node
+    fn major(&mut self, node: &Option<Rc<RefCell<Node>>>, path: &mut PosID) -> Option<String> {
+        let node = node.as_ref()?.borrow();
+        let id = self.new_id();
+        let label = if path.0.is_empty() {
+            "root".to_string()
+        } else {
+            path.to_string()
+        };
+        writeln!(
+            self.out,
+            "  {id} [shape=circle, label=\"{}\\nlen {}\"];",
+            escape(&label),
+            node.len.chars
+        )
+        .unwrap();
+
+        if let Some(left) = self.child(&node.left, path, 0) {
+            self.edge(&id, &left, "label=\"L\"");
+        }
+        for mini in node.children.borrow().iter() {
+            let mini = mini.borrow();
+            path.0.push(PathComponent(0, Some(mini.disambiguator)));
+            let mini_id = self.new_id();
+            let style = if mini.tombstone {
+                ", style=\"dashed,filled\", fillcolor=lightgrey"
+            } else if mini.moved {
+                ", style=dotted"
+            } else {
+                ""
+            };
+            writeln!(
+                self.out,
+                "  {mini_id} [shape=box{style}, label=\"{} ({})\"];",
+                escape(&mini.atom.label()),
+                mini.disambiguator
+            )
+            .unwrap();
+            self.edge(&id, &mini_id, "style=dotted, arrowhead=none");
+            if let Some(left) = self.child(&mini.left, path, 0) {
+                self.edge(&mini_id, &left, "label=\"L\"");
+            }
+            if let Some(right) = self.child(&mini.right, path, 1) {
+                self.edge(&mini_id, &right, "label=\"R\"");
+            }
+            path.0.pop();
+        }
+        if let Some(right) = self.child(&node.right, path, 1) {
+            self.edge(&id, &right, "label=\"R\"");
+        }
+        Some(id)
+    }
+
+    fn child(
+        &mut self,
+        node: &Option<Rc<RefCell<Node>>>,
+        path: &mut PosID,
+        dir: usize,
+    ) -> Option<String> {
+        path.0.push(PathComponent(dir, None));
+        let id = self.major(node, path);
+        path.0.pop();
+        id
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
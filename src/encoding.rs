@@ -0,0 +1,491 @@
+use std::{
+    collections::BTreeMap,
+    io::{Error, ErrorKind, Result},
+};
+
+use crate::{
+    marks::{Anchor, Mark, MarkOp, MarkSignal, MarkType},
+    moves::MoveSignal,
+    node::Atom,
+    oplog::{OpId, VersionVector},
+    pos_id::{CompactPosID, PathComponent, PosID},
+    treedoc::{DeleteSignal, InsertSignal, Signal},
+};
+
+/*
+Compact binary encoding for everything that travels between replicas or to disk.
+Integers are LEB128 varints, strings and sequences are length-prefixed and
+enums start with a one byte tag.
+*/
+
+pub trait Encode {
+    fn encode(&self, buf: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    /// Reads one value from the front of `buf`, advancing it
+    fn decode(buf: &mut &[u8]) -> Result<Self>;
+}
+
+pub fn to_bytes<T: Encode>(value: &T) -> Vec<u8> {
+    let mut buf = Vec::new();
+    value.encode(&mut buf);
+    buf
+}
+
+/// Decodes a value that must span all of `bytes`
+pub fn from_bytes<T: Decode>(mut bytes: &[u8]) -> Result<T> {
+    let value = T::decode(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    Ok(value)
+}
+
+pub(crate) fn read_u8(buf: &mut &[u8]) -> Result<u8> {
+    let (first, rest) = buf
+        .split_first()
+        .ok_or(Error::from(ErrorKind::UnexpectedEof))?;
+    *buf = rest;
+    Ok(*first)
+}
+
+fn read_bytes<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(Error::from(ErrorKind::UnexpectedEof));
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(bytes)
+}
+
+impl Encode for u64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let mut value = *self;
+        while value >= 0x80 {
+            buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+}
+
+impl Decode for u64 {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = read_u8(buf)?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::from(ErrorKind::InvalidData))
+    }
+}
+
+impl Encode for usize {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (*self as u64).encode(buf)
+    }
+}
+
+impl Decode for usize {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        usize::try_from(u64::decode(buf)?).map_err(|_| Error::from(ErrorKind::InvalidData))
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.len().encode(buf);
+        buf.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let len = usize::decode(buf)?;
+        let bytes = read_bytes(buf, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::from(ErrorKind::InvalidData))
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.len().encode(buf);
+        for item in self {
+            item.encode(buf);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let len = usize::decode(buf)?;
+        // Every item takes at least a byte, don't trust the length any further
+        let mut items = Vec::with_capacity(len.min(buf.len()));
+        for _ in 0..len {
+            items.push(T::decode(buf)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf);
+        self.1.encode(buf);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok((A::decode(buf)?, B::decode(buf)?))
+    }
+}
+
+impl Encode for Atom {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Atom::Char(ch) => {
+                buf.push(0);
+                u64::from(*ch).encode(buf);
+            }
+            Atom::Embed(id, metadata) => {
+                buf.push(1);
+                id.encode(buf);
+                metadata.len().encode(buf);
+                for (key, value) in metadata {
+                    key.encode(buf);
+                    value.encode(buf);
+                }
+            }
+        }
+    }
+}
+
+impl Decode for Atom {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match read_u8(buf)? {
+            0 => u32::try_from(u64::decode(buf)?)
+                .ok()
+                .and_then(char::from_u32)
+                .map(Atom::Char)
+                .ok_or(Error::from(ErrorKind::InvalidData)),
+            1 => {
+                let id = String::decode(buf)?;
+                let mut metadata = BTreeMap::new();
+                for _ in 0..usize::decode(buf)? {
+                    metadata.insert(String::decode(buf)?, String::decode(buf)?);
+                }
+                Ok(Atom::Embed(id, metadata))
+            }
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+impl Encode for PathComponent {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self.1 {
+            None => {
+                buf.push(0);
+                self.0.encode(buf);
+            }
+            Some(dis) => {
+                buf.push(1);
+                self.0.encode(buf);
+                dis.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for PathComponent {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match read_u8(buf)? {
+            0 => Ok(PathComponent(usize::decode(buf)?, None)),
+            1 => Ok(PathComponent(usize::decode(buf)?, Some(u64::decode(buf)?))),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+// PosIDs travel bit-packed, see `CompactPosID`
+impl Encode for PosID {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        CompactPosID::from(self).encode(buf)
+    }
+}
+
+impl Decode for PosID {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok(CompactPosID::decode(buf)?.to_pos_id())
+    }
+}
+
+impl Encode for CompactPosID {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_bytes().len().encode(buf);
+        buf.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for CompactPosID {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let len = usize::decode(buf)?;
+        CompactPosID::from_bytes(read_bytes(buf, len)?)
+    }
+}
+
+impl Encode for Mark {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Mark::Bold => buf.push(0),
+            Mark::Italic => buf.push(1),
+            Mark::Link(url) => {
+                buf.push(2);
+                url.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Mark {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match read_u8(buf)? {
+            0 => Ok(Mark::Bold),
+            1 => Ok(Mark::Italic),
+            2 => Ok(Mark::Link(String::decode(buf)?)),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+impl Encode for MarkType {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(match self {
+            MarkType::Bold => 0,
+            MarkType::Italic => 1,
+            MarkType::Link => 2,
+        })
+    }
+}
+
+impl Decode for MarkType {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match read_u8(buf)? {
+            0 => Ok(MarkType::Bold),
+            1 => Ok(MarkType::Italic),
+            2 => Ok(MarkType::Link),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+impl Encode for Anchor {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Anchor::Before(pos_id) => {
+                buf.push(1);
+                pos_id.encode(buf);
+            }
+            Anchor::After(pos_id) => {
+                buf.push(2);
+                pos_id.encode(buf);
+            }
+            Anchor::DocEnd => buf.push(3),
+        }
+    }
+}
+
+impl Decode for Anchor {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match read_u8(buf)? {
+            1 => Ok(Anchor::Before(CompactPosID::decode(buf)?)),
+            2 => Ok(Anchor::After(CompactPosID::decode(buf)?)),
+            3 => Ok(Anchor::DocEnd),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+impl Encode for InsertSignal {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.atom.encode(buf);
+        self.pos_id.encode(buf);
+        self.unique_disambiguator.encode(buf);
+        self.lamport.encode(buf);
+        self.grow.encode(buf);
+    }
+}
+
+impl Decode for InsertSignal {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok(InsertSignal {
+            atom: Atom::decode(buf)?,
+            pos_id: CompactPosID::decode(buf)?,
+            unique_disambiguator: u64::decode(buf)?,
+            lamport: u64::decode(buf)?,
+            grow: usize::decode(buf)?,
+        })
+    }
+}
+
+impl Encode for DeleteSignal {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.pos_id.encode(buf);
+        self.unique_disambiguator.encode(buf);
+        self.lamport.encode(buf);
+    }
+}
+
+impl Decode for DeleteSignal {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok(DeleteSignal {
+            pos_id: CompactPosID::decode(buf)?,
+            unique_disambiguator: u64::decode(buf)?,
+            lamport: u64::decode(buf)?,
+        })
+    }
+}
+
+impl Encode for MarkSignal {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.id.0.encode(buf);
+        self.id.1.encode(buf);
+        self.start.encode(buf);
+        self.end.encode(buf);
+        match &self.op {
+            MarkOp::Add(mark) => {
+                buf.push(0);
+                mark.encode(buf);
+            }
+            MarkOp::Remove(mark_type) => {
+                buf.push(1);
+                mark_type.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for MarkSignal {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let id = (u64::decode(buf)?, u64::decode(buf)?);
+        let start = Anchor::decode(buf)?;
+        let end = Anchor::decode(buf)?;
+        let op = match read_u8(buf)? {
+            0 => MarkOp::Add(Mark::decode(buf)?),
+            1 => MarkOp::Remove(MarkType::decode(buf)?),
+            _ => return Err(Error::from(ErrorKind::InvalidData)),
+        };
+        Ok(MarkSignal { id, start, end, op })
+    }
+}
+
+impl Encode for Signal {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Signal::Insert(op) => {
+                buf.push(0);
+                op.encode(buf);
+            }
+            Signal::Delete(op) => {
+                buf.push(1);
+                op.encode(buf);
+            }
+            Signal::Mark(op) => {
+                buf.push(2);
+                op.encode(buf);
+            }
+            Signal::Transaction(sigs) => {
+                buf.push(3);
+                sigs.encode(buf);
+            }
+            Signal::Move(op) => {
+                buf.push(4);
+                op.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Signal {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match read_u8(buf)? {
+            0 => Ok(Signal::Insert(InsertSignal::decode(buf)?)),
+            1 => Ok(Signal::Delete(DeleteSignal::decode(buf)?)),
+            2 => Ok(Signal::Mark(MarkSignal::decode(buf)?)),
+            3 => {
+                let len = usize::decode(buf)?;
+                let mut sigs = Vec::with_capacity(len.min(buf.len()));
+                for _ in 0..len {
+                    // Transactions don't nest, which also keeps this from recursing
+                    if buf.first() == Some(&3) {
+                        return Err(Error::from(ErrorKind::InvalidData));
+                    }
+                    sigs.push(Signal::decode(buf)?);
+                }
+                Ok(Signal::Transaction(sigs))
+            }
+            4 => Ok(Signal::Move(MoveSignal::decode(buf)?)),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+impl Encode for MoveSignal {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.id.0.encode(buf);
+        self.id.1.encode(buf);
+        self.origin.encode(buf);
+        self.slot.encode(buf);
+    }
+}
+
+impl Decode for MoveSignal {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok(MoveSignal {
+            id: (u64::decode(buf)?, u64::decode(buf)?),
+            origin: CompactPosID::decode(buf)?,
+            slot: InsertSignal::decode(buf)?,
+        })
+    }
+}
+
+impl Encode for OpId {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.site.encode(buf);
+        self.seq.encode(buf);
+    }
+}
+
+impl Decode for OpId {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok(OpId {
+            site: u64::decode(buf)?,
+            seq: u64::decode(buf)?,
+        })
+    }
+}
+
+impl Encode for VersionVector {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.len().encode(buf);
+        for (site, seq) in &self.0 {
+            site.encode(buf);
+            seq.encode(buf);
+        }
+    }
+}
+
+impl Decode for VersionVector {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let mut version = VersionVector::new();
+        for _ in 0..usize::decode(buf)? {
+            version.0.insert(u64::decode(buf)?, u64::decode(buf)?);
+        }
+        Ok(version)
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fmt, ops::Range};
+
+use crate::treedoc::Treedoc;
+
+/*
+Applications patch their view from these instead of re-rendering: every signal
+applied, local or remote, that changes what is visible is reported in visible
+char indices, right after it took effect. Signals that change nothing visible
+(deleting a tombstone again, a duplicate mark) are not reported.
+*/
+
+/// What applying a signal did to the visible text
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// `text` now starts at `index`; embeds show up as U+FFFC
+    Inserted { index: usize, text: String },
+    /// `len` chars starting at `index` are gone
+    Deleted { index: usize, len: usize },
+    /// Formatting may have changed for the chars in `range`
+    Formatted { range: Range<usize> },
+    /// Everything one transaction did, in the order it happened
+    Transaction(Vec<Change>),
+}
+
+/// Handle for removing a subscription again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(usize);
+
+type Callback = Box<dyn FnMut(&Change)>;
+
+#[derive(Default)]
+pub struct Subscribers {
+    callbacks: Vec<(SubscriptionId, Callback)>,
+    next_id: usize,
+    // Changes held back while a transaction is applied
+    batch: Option<Vec<Change>>,
+}
+
+impl fmt::Debug for Subscribers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Subscribers({})", self.callbacks.len())
+    }
+}
+
+impl Subscribers {
+    pub fn is_empty(&self) -> bool {
+        self.callbacks.is_empty()
+    }
+
+    // Holds back changes until `end_batch`, returns the batch it interrupted
+    pub(crate) fn begin_batch(&mut self) -> Option<Vec<Change>> {
+        self.batch.replace(Vec::new())
+    }
+
+    pub(crate) fn end_batch(&mut self, outer: Option<Vec<Change>>) -> Vec<Change> {
+        std::mem::replace(&mut self.batch, outer).unwrap_or_default()
+    }
+}
+
+impl Treedoc {
+    /// Calls `f` with every change applied signals make to the visible text
+    pub fn subscribe(&mut self, f: impl FnMut(&Change) + 'static) -> SubscriptionId {
+        let id = SubscriptionId(self.subscribers.next_id);
+        self.subscribers.next_id += 1;
+        self.subscribers.callbacks.push((id, Box::new(f)));
+        id
+    }
+
+    /// Returns false if there was no such subscription
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let before = self.subscribers.callbacks.len();
+        self.subscribers.callbacks.retain(|(i, _)| *i != id);
+        self.subscribers.callbacks.len() != before
+    }
+
+    pub(crate) fn emit(&mut self, change: Change) {
+        if let Some(batch) = &mut self.subscribers.batch {
+            batch.push(change);
+            return;
+        }
+        for (_, callback) in self.subscribers.callbacks.iter_mut() {
+            callback(&change);
+        }
+    }
+}
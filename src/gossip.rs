@@ -0,0 +1,155 @@
+use std::io::{Error, ErrorKind, Result};
+
+use crate::{
+    encoding::{Decode, Encode, read_u8},
+    node::SDIS,
+    oplog::{OpId, OpLog, VersionVector},
+    treedoc::{Signal, Treedoc},
+};
+
+/*
+Anti-entropy for peer-to-peer setups without a relay. Every `tick` a node sends
+its version vector to one random peer. The receiver answers with the ops the
+sender is missing and, if the sender knows something it doesn't, with its own
+digest so the sender pulls those back. Lost messages just leave the replicas
+apart until a later round picks the same pair again.
+
+There is no transport here: `tick` and `receive` hand back the messages to send
+and the caller moves them however it likes.
+*/
+
+#[derive(Debug, Clone)]
+pub enum GossipMessage {
+    Digest(VersionVector),
+    Ops(Vec<(OpId, Signal)>),
+}
+
+/// A replica that converges with its peers by periodic pairwise exchanges
+pub struct GossipNode {
+    pub doc: Treedoc,
+    pub log: OpLog,
+    site: SDIS,
+    peers: Vec<SDIS>,
+    // Received ops waiting for their causal predecessors
+    pending: Vec<(OpId, Signal, ())>,
+    rng: u64,
+}
+
+impl Encode for GossipMessage {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            GossipMessage::Digest(version) => {
+                buf.push(0);
+                version.encode(buf);
+            }
+            GossipMessage::Ops(ops) => {
+                buf.push(1);
+                ops.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for GossipMessage {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match read_u8(buf)? {
+            0 => Ok(GossipMessage::Digest(VersionVector::decode(buf)?)),
+            1 => Ok(GossipMessage::Ops(Vec::decode(buf)?)),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+impl GossipNode {
+    /// An empty document edited as `site`. `seed` drives the choice of peers,
+    /// so runs with the same seed pick the same partners.
+    pub fn new(site: SDIS, seed: u64) -> Self {
+        let doc = Treedoc {
+            unique_disambiguator: site,
+            ..Treedoc::default()
+        };
+        Self {
+            doc,
+            log: OpLog::new(),
+            site,
+            peers: Vec::new(),
+            pending: Vec::new(),
+            // xorshift never leaves zero
+            rng: seed | 1,
+        }
+    }
+
+    pub fn site(&self) -> SDIS {
+        self.site
+    }
+
+    pub fn add_peer(&mut self, site: SDIS) {
+        if site != self.site && !self.peers.contains(&site) {
+            self.peers.push(site);
+        }
+    }
+
+    pub fn peers(&self) -> &[SDIS] {
+        &self.peers
+    }
+
+    /// Applies a signal made on this replica. It spreads with the next rounds.
+    pub fn apply_local(&mut self, sig: Signal) -> Result<OpId> {
+        self.doc.apply(sig.clone())?;
+        Ok(self.log.push_local(self.site, sig))
+    }
+
+    /// Starts a round: our digest for a random peer, if we know any
+    pub fn tick(&mut self) -> Option<(SDIS, GossipMessage)> {
+        if self.peers.is_empty() {
+            return None;
+        }
+        let idx = self.next_random() % self.peers.len() as u64;
+        Some((
+            self.peers[idx as usize],
+            GossipMessage::Digest(self.log.version().clone()),
+        ))
+    }
+
+    /// Handles a message from `from` and returns the replies to send back to it
+    pub fn receive(&mut self, from: SDIS, msg: GossipMessage) -> Result<Vec<GossipMessage>> {
+        let mut replies = Vec::new();
+        match msg {
+            GossipMessage::Digest(version) => {
+                let missing = self.log.missing_for(&version);
+                if !missing.is_empty() {
+                    replies.push(GossipMessage::Ops(missing));
+                }
+                if !self.log.version().dominates(&version) {
+                    replies.push(GossipMessage::Digest(self.log.version().clone()));
+                }
+            }
+            GossipMessage::Ops(ops) => {
+                for (op_id, sig) in ops {
+                    if !self.log.contains(&op_id)
+                        && self.pending.iter().all(|(p, _, _)| *p != op_id)
+                    {
+                        self.pending.push((op_id, sig, ()));
+                    }
+                }
+                let rejected = self
+                    .log
+                    .deliver(&mut self.doc, &mut self.pending, |_, _, _| {});
+                if !rejected.is_empty() {
+                    return Err(Error::from(ErrorKind::InvalidData));
+                }
+            }
+        }
+        // Whoever talks to us is worth gossiping with
+        self.add_peer(from);
+        Ok(replies)
+    }
+
+    // xorshift64
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
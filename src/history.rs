@@ -0,0 +1,165 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+};
+
+use crate::{
+    iter::AllMinis,
+    node::{Mininode, SDIS},
+    treedoc::{Signal, Treedoc},
+};
+
+/*
+Time travel. Tombstones keep the stamps of the inserts and deletes that made
+them (see `blame`), so the text as it was at any earlier version can be read
+back from the current tree. A version is a `ClockVector`: for every site the
+Lamport clock of its latest edit included. A replica's own is
+`Treedoc::version`, save a copy of it to come back to that point later. Every
+edit of a site has a higher clock than the ones before it, and they are
+delivered in order, so the vector says exactly which edits happened before.
+*/
+
+/// Lamport clock of the latest edit included from every site. Not to be mixed
+/// up with the op log's `VersionVector`, which counts ops per site.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClockVector(pub BTreeMap<SDIS, u64>);
+
+/// A stretch of text in a diff between two versions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffRun {
+    /// In both versions
+    Kept(String),
+    /// Only in the later version
+    Inserted(String),
+    /// Only in the earlier version
+    Deleted(String),
+}
+
+// (lamport, site) of an edit
+type Stamp = (u64, SDIS);
+type MiniPtr = *const RefCell<Mininode>;
+
+impl Signal {
+    // Stamps of every edit in the signal
+    pub(crate) fn stamps(&self, out: &mut Vec<Stamp>) {
+        match self {
+            Signal::Insert(op) => out.push((op.lamport, op.unique_disambiguator)),
+            Signal::Delete(op) => out.push((op.lamport, op.unique_disambiguator)),
+            Signal::Mark(op) => out.push(op.id),
+            Signal::Move(op) => {
+                out.push((op.slot.lamport, op.slot.unique_disambiguator));
+                out.push(op.id);
+            }
+            Signal::Transaction(sigs) => sigs.iter().for_each(|sig| sig.stamps(out)),
+        }
+    }
+}
+
+impl ClockVector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, site: SDIS) -> u64 {
+        self.0.get(&site).copied().unwrap_or(0)
+    }
+
+    /// Whether the edit stamped `(lamport, site)` is part of this version
+    pub fn includes(&self, (lamport, site): Stamp) -> bool {
+        self.get(site) >= lamport
+    }
+
+    pub(crate) fn observe(&mut self, (lamport, site): Stamp) {
+        let clock = self.0.entry(site).or_insert(0);
+        *clock = (*clock).max(lamport);
+    }
+}
+
+// Where moved atoms are shown: at the slot of their latest move a version
+// includes, or where they were inserted if it includes none
+struct Moves {
+    // slot -> (its move, the moved atom)
+    slots: HashMap<MiniPtr, (Stamp, Rc<RefCell<Mininode>>)>,
+    // moved atom -> all its moves
+    of_atom: HashMap<MiniPtr, Vec<Stamp>>,
+}
+
+impl Moves {
+    fn new(td: &Treedoc) -> Self {
+        let mut moves = Moves {
+            slots: HashMap::new(),
+            of_atom: HashMap::new(),
+        };
+        for op in &td.moves {
+            let (Some(slot), Some(origin)) = (
+                td.mini_at(&op.slot.atom_path()),
+                td.mini_at(&op.origin.to_pos_id()),
+            ) else {
+                continue;
+            };
+            moves
+                .of_atom
+                .entry(Rc::as_ptr(&origin))
+                .or_default()
+                .push(op.id);
+            moves.slots.insert(Rc::as_ptr(&slot), (op.id, origin));
+        }
+        moves
+    }
+
+    fn visible(&self, mini: &Rc<RefCell<Mininode>>, version: &ClockVector) -> bool {
+        let (atom, slot_of) = match self.slots.get(&Rc::as_ptr(mini)) {
+            Some((id, origin)) => (origin, Some(*id)),
+            None => (mini, None),
+        };
+        let atom_ref = atom.borrow();
+        if !version.includes(atom_ref.inserted)
+            || atom_ref.deleted.iter().any(|d| version.includes(*d))
+        {
+            return false;
+        }
+        let latest = self.of_atom.get(&Rc::as_ptr(atom)).and_then(|ids| {
+            ids.iter()
+                .filter(|id| version.includes(**id))
+                .max()
+                .copied()
+        });
+        latest == slot_of
+    }
+}
+
+impl Treedoc {
+    /// The visible text as it was at `version`
+    pub fn text_at(&self, version: &ClockVector) -> String {
+        let moves = Moves::new(self);
+        AllMinis::new(self)
+            .filter(|mini| moves.visible(mini, version))
+            .map(|mini| mini.borrow().atom.to_char())
+            .collect()
+    }
+
+    /// What changed in the text from version `from` to version `to`, in document
+    /// order. `Kept` and `Deleted` runs make up the text at `from`, `Kept` and
+    /// `Inserted` ones the text at `to`.
+    pub fn diff_versions(&self, from: &ClockVector, to: &ClockVector) -> Vec<DiffRun> {
+        let moves = Moves::new(self);
+        let mut runs: Vec<DiffRun> = Vec::new();
+        for mini in AllMinis::new(self) {
+            let ch = mini.borrow().atom.to_char();
+            let run = match (moves.visible(&mini, from), moves.visible(&mini, to)) {
+                (true, true) => DiffRun::Kept,
+                (false, true) => DiffRun::Inserted,
+                (true, false) => DiffRun::Deleted,
+                (false, false) => continue,
+            };
+            match (runs.last_mut(), run(String::new())) {
+                (Some(DiffRun::Kept(text)), DiffRun::Kept(_))
+                | (Some(DiffRun::Inserted(text)), DiffRun::Inserted(_))
+                | (Some(DiffRun::Deleted(text)), DiffRun::Deleted(_)) => text.push(ch),
+                _ => runs.push(run(ch.to_string())),
+            }
+        }
+        runs
+    }
+}
@@ -0,0 +1,229 @@
+use std::{cell::RefCell, marker::PhantomData, rc::Rc};
+
+use crate::{
+    node::{Atom, Mininode, Node},
+    pos_id::{PathComponent, PosID},
+    treedoc::Treedoc,
+};
+
+enum Step {
+    Major(Rc<RefCell<Node>>),
+    Mini(Rc<RefCell<Mininode>>),
+    Atom(Rc<RefCell<Mininode>>),
+}
+
+// A pending step plus where its path starts: truncate to `depth`, then push `comp`
+struct Frame {
+    step: Step,
+    depth: usize,
+    comp: Option<PathComponent>,
+}
+
+// Lazy infix walk over the tree. Only the right spine of the current position
+// lives on the stack, and whole subtrees are skipped by their cached lengths
+// when seeking with `nth`.
+struct Walker {
+    stack: Vec<Frame>,
+    path: PosID,
+    to_skip: usize,
+    // Also yield tombstones and hidden move slots, which lengths don't count
+    hidden: bool,
+}
+
+/// PosIDs of the visible atoms in document order
+pub struct TreedocIter<'a> {
+    walker: Walker,
+    _doc: PhantomData<&'a Treedoc>,
+}
+
+/// Visible atoms in document order
+pub struct Atoms<'a> {
+    walker: Walker,
+    _doc: PhantomData<&'a Treedoc>,
+}
+
+/// Visible atoms in document order along with their PosIDs
+pub struct AtomsWithIds<'a> {
+    walker: Walker,
+    _doc: PhantomData<&'a Treedoc>,
+}
+
+// Every mininode in document order, tombstones and hidden move slots included
+pub(crate) struct AllMinis<'a> {
+    walker: Walker,
+    _doc: PhantomData<&'a Treedoc>,
+}
+
+impl Walker {
+    fn new(root: &Option<Rc<RefCell<Node>>>) -> Self {
+        let mut stack = Vec::new();
+        if let Some(root) = root {
+            stack.push(Frame {
+                step: Step::Major(root.clone()),
+                depth: 0,
+                comp: None,
+            });
+        }
+        Self {
+            stack,
+            path: PosID::new(),
+            to_skip: 0,
+            hidden: false,
+        }
+    }
+
+    fn push_major(&mut self, node: &Option<Rc<RefCell<Node>>>, depth: usize, dir: usize) {
+        if let Some(node) = node {
+            self.stack.push(Frame {
+                step: Step::Major(node.clone()),
+                depth,
+                comp: Some(PathComponent(dir, None)),
+            });
+        }
+    }
+
+    fn next_mini(&mut self) -> Option<Rc<RefCell<Mininode>>> {
+        while let Some(frame) = self.stack.pop() {
+            self.path.0.truncate(frame.depth);
+            if let Some(comp) = frame.comp {
+                self.path.0.push(comp);
+            }
+            let depth = self.path.0.len();
+            match frame.step {
+                Step::Major(node) => {
+                    let node = node.borrow();
+                    if !self.hidden && self.to_skip >= node.len.chars {
+                        self.to_skip -= node.len.chars;
+                        continue;
+                    }
+                    self.push_major(&node.right, depth, 1);
+                    for mini in node.children.borrow().iter().rev() {
+                        self.stack.push(Frame {
+                            step: Step::Mini(mini.clone()),
+                            depth,
+                            comp: Some(PathComponent(0, Some(mini.borrow().disambiguator))),
+                        });
+                    }
+                    self.push_major(&node.left, depth, 0);
+                }
+                Step::Mini(mini) => {
+                    let mini_ref = mini.borrow();
+                    if !self.hidden && self.to_skip >= mini_ref.len.chars {
+                        self.to_skip -= mini_ref.len.chars;
+                        continue;
+                    }
+                    self.push_major(&mini_ref.right, depth, 1);
+                    self.stack.push(Frame {
+                        step: Step::Atom(mini.clone()),
+                        depth,
+                        comp: None,
+                    });
+                    self.push_major(&mini_ref.left, depth, 0);
+                }
+                Step::Atom(mini) => {
+                    if !self.hidden && !mini.borrow().is_visible() {
+                        continue;
+                    }
+                    if self.to_skip > 0 {
+                        self.to_skip -= 1;
+                        continue;
+                    }
+                    return Some(mini);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<'a> TreedocIter<'a> {
+    pub(crate) fn new(td: &'a Treedoc) -> Self {
+        Self {
+            walker: Walker::new(&td.root),
+            _doc: PhantomData,
+        }
+    }
+}
+
+impl<'a> Atoms<'a> {
+    pub(crate) fn new(td: &'a Treedoc) -> Self {
+        Self {
+            walker: Walker::new(&td.root),
+            _doc: PhantomData,
+        }
+    }
+}
+
+impl<'a> AtomsWithIds<'a> {
+    pub(crate) fn new(td: &'a Treedoc) -> Self {
+        Self {
+            walker: Walker::new(&td.root),
+            _doc: PhantomData,
+        }
+    }
+}
+
+impl<'a> AllMinis<'a> {
+    pub(crate) fn new(td: &'a Treedoc) -> Self {
+        let mut walker = Walker::new(&td.root);
+        walker.hidden = true;
+        Self {
+            walker,
+            _doc: PhantomData,
+        }
+    }
+}
+
+impl Iterator for TreedocIter<'_> {
+    type Item = PosID;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walker.next_mini()?;
+        Some(self.walker.path.clone())
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.walker.to_skip += n;
+        self.next()
+    }
+}
+
+impl Iterator for Atoms<'_> {
+    type Item = Atom;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walker
+            .next_mini()
+            .map(|mini| mini.borrow().atom.clone())
+    }
+
+    // Seeks by subtree lengths, which also makes `skip` cheap
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.walker.to_skip += n;
+        self.next()
+    }
+}
+
+impl Iterator for AtomsWithIds<'_> {
+    type Item = (PosID, Atom);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mini = self.walker.next_mini()?;
+        let atom = mini.borrow().atom.clone();
+        // The walker's path already ends at the mininode
+        Some((self.walker.path.clone(), atom))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.walker.to_skip += n;
+        self.next()
+    }
+}
+
+impl Iterator for AllMinis<'_> {
+    type Item = Rc<RefCell<Mininode>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walker.next_mini()
+    }
+}
@@ -1,3 +1,29 @@
+pub mod allocation;
+pub mod blame;
+pub mod branch;
+pub mod dot;
+pub mod encoding;
+pub mod events;
+pub mod gossip;
+pub mod history;
+pub mod iter;
+pub mod logoot;
+pub mod marks;
+pub mod moves;
 pub mod node;
+pub mod offset;
+pub mod oplog;
 pub mod pos_id;
+#[cfg(feature = "sync")]
+pub mod relay;
+pub mod repl;
+pub mod rga;
+pub mod sequence;
+pub mod stats;
+#[cfg(feature = "sync")]
+pub mod sync;
+pub mod trace;
+pub mod transaction;
 pub mod treedoc;
+#[cfg(feature = "websocket")]
+pub mod websocket;
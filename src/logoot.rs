@@ -0,0 +1,160 @@
+use std::io::{Error, ErrorKind, Result};
+
+use crate::{
+    node::{Atom, SDIS},
+    sequence::{SequenceCrdt, check_gap},
+};
+
+/*
+Logoot (Weiss et al.) with the LSEQ allocation strategy (Nédelec et al.). An
+atom's position is a list of (digit, site, clock) idents compared
+lexicographically, a prefix before its extensions. A new position takes the
+shortest digit path between its neighbours; at depth `d` digits run up to
+`16 << d` and are picked near the left or the right neighbour depending on the
+depth, so both typing forwards and backwards keep positions short.
+
+Positions are dense and unique, so deleted atoms are dropped right away, no
+tombstones. A delete of a position that is gone was a concurrent delete of the
+same atom.
+*/
+
+// How far from a neighbour a new digit may land
+const BOUNDARY: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ident {
+    pub digit: u64,
+    pub site: SDIS,
+    pub clock: u64,
+}
+
+/// Position of an atom, ordered like the document
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position(pub Vec<Ident>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogootOp {
+    Insert(Position, Atom),
+    Delete(Position),
+}
+
+#[derive(Debug, Clone)]
+pub struct Logoot {
+    site: SDIS,
+    // Positions handed out so far
+    clock: u64,
+    // Sorted by position
+    atoms: Vec<(Position, Atom)>,
+    rng: u64,
+}
+
+fn base(depth: usize) -> u64 {
+    16 << depth.min(48)
+}
+
+impl Logoot {
+    // xorshift64, any spread will do
+    fn next_rand(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    // A position strictly between `p` and `q`, None for the end of the document
+    fn between(&mut self, mut p: &[Ident], mut q: Option<&[Ident]>) -> Position {
+        let (site, clock) = (self.site, self.clock);
+        let own = |digit| Ident { digit, site, clock };
+        let mut prefix = Vec::new();
+        for depth in 0.. {
+            let lo = p.first().map_or(0, |i| i.digit);
+            let hi = q.map_or(base(depth), |q| q[0].digit);
+            if hi - lo > 1 {
+                let step = 1 + self.next_rand() % (hi - lo - 1).min(BOUNDARY);
+                let digit = if depth % 2 == 0 { lo + step } else { hi - step };
+                prefix.push(own(digit));
+                break;
+            }
+            match (p.split_first(), q) {
+                // Follow `p`. Once it branches off below `q`, `q` bounds nothing.
+                (Some((first, rest)), _) => {
+                    prefix.push(*first);
+                    q = q.filter(|q| q[0] == *first).map(|q| &q[1..]);
+                    p = rest;
+                }
+                // Follow `q` as long as it goes deeper
+                (None, Some(q_all)) if q_all.len() > 1 => {
+                    prefix.push(q_all[0]);
+                    q = Some(&q_all[1..]);
+                }
+                // `q` ends in digit 1 here, digit 0 is still below it
+                (None, _) => {
+                    prefix.push(own(0));
+                    q = None;
+                }
+            }
+        }
+        Position(prefix)
+    }
+}
+
+impl SequenceCrdt for Logoot {
+    type Op = LogootOp;
+
+    fn with_site(site: SDIS) -> Self {
+        Logoot {
+            site,
+            clock: 0,
+            atoms: Vec::new(),
+            rng: site.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+        }
+    }
+
+    fn site(&self) -> SDIS {
+        self.site
+    }
+
+    fn insert(&mut self, index: usize, atom: Atom) -> Result<LogootOp> {
+        check_gap(index, self.atoms.len())?;
+        self.clock += 1;
+        let p = match index {
+            0 => Vec::new(),
+            _ => self.atoms[index - 1].0.0.clone(),
+        };
+        let q = self.atoms.get(index).map(|(pos, _)| pos.0.clone());
+        let pos = self.between(&p, q.as_deref());
+        Ok(LogootOp::Insert(pos, atom))
+    }
+
+    fn delete(&mut self, index: usize) -> Result<LogootOp> {
+        let (pos, _) = self
+            .atoms
+            .get(index)
+            .ok_or(Error::from(ErrorKind::InvalidInput))?;
+        Ok(LogootOp::Delete(pos.clone()))
+    }
+
+    fn apply(&mut self, op: LogootOp) -> Result<()> {
+        match op {
+            LogootOp::Insert(pos, atom) => {
+                if let Err(i) = self.atoms.binary_search_by(|(p, _)| p.cmp(&pos)) {
+                    self.atoms.insert(i, (pos, atom));
+                }
+            }
+            LogootOp::Delete(pos) => {
+                if let Ok(i) = self.atoms.binary_search_by(|(p, _)| p.cmp(&pos)) {
+                    self.atoms.remove(i);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.atoms.len()
+    }
+
+    fn atoms(&self) -> Vec<Atom> {
+        self.atoms.iter().map(|(_, atom)| atom.clone()).collect()
+    }
+}
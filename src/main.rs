@@ -1,36 +1,40 @@
-use simple_text_crdt::{node::Node, treedoc::Signal, treedoc::Treedoc};
-use std::{cell::RefCell, io::Error, rc::Rc};
+use simple_text_crdt::repl::Repl;
+use std::io::{self, BufRead, Error, Write};
 
+// With arguments, runs them as one command and exits, e.g. `replay bug.txt`.
+// After `-c` they are `;` separated commands, e.g. `-c 'load doc.bin; dot doc.dot'`.
 fn main() -> Result<(), Error> {
-    let root = Node::new_with_mini('f', 1u64);
-
-    let mut td = Treedoc {
-        root: Some(Rc::new(RefCell::new(root))),
-        doc_length: 1,
-        unique_disambiguator: 1u64,
-    };
-
-    let sig = td.insert(0, 'e')?;
-    td.apply(Signal::Insert(sig))?;
-
-    let sig = td.insert(0, 'd')?;
-    td.apply(Signal::Insert(sig))?;
-
-    let sig = td.insert(3, 'c')?;
-    td.apply(Signal::Insert(sig))?;
-
-    let sig = td.insert(0, 'b')?;
-    td.apply(Signal::Insert(sig))?;
-
-    let sig = td.insert(0, 'a')?;
-    td.apply(Signal::Insert(sig))?;
-
-    let sig = td.delete(td.doc_length)?;
-    td.apply(Signal::Delete(sig))?;
-
-    let mut nodes = Vec::new();
-    Treedoc::traverse_in_and_collect(&td.root, &mut nodes);
-    println!("{:?}", nodes);
-
+    let mut repl = Repl::new();
+    let mut stdout = io::stdout();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.split_first() {
+        Some((flag, cmds)) if flag == "-c" => {
+            for cmd in cmds.join(" ").split(';') {
+                repl.execute(cmd, &mut stdout)?;
+            }
+            return Ok(());
+        }
+        // Taken as typed, so `insert 0 a;b` inserts the `;` too
+        Some(_) => {
+            repl.execute(&args.join(" "), &mut stdout)?;
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        write!(stdout, "> ")?;
+        stdout.flush()?;
+        let Some(line) = lines.next() else {
+            break;
+        };
+        match repl.execute(&line?, &mut stdout) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => writeln!(stdout, "error: {e}")?,
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,178 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    ops::Range,
+};
+
+use crate::{events::Change, node::SDIS, pos_id::CompactPosID, treedoc::Treedoc};
+
+/*
+Peritext-style formatting: a mark is not stored on the atoms, it is an operation
+whose ends are anchored to the gaps around PosIDs. Anchors keep resolving after
+the atoms they point to are deleted, and text inserted inside the anchors
+picks up the formatting without any extra operations.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MarkType {
+    Bold,
+    Italic,
+    Link,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Mark {
+    Bold,
+    Italic,
+    Link(String),
+}
+
+/// Gap in the document a mark starts or ends at. Marks never grow at their
+/// start, so there is no anchor for the start of the document.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anchor {
+    /// Just before the atom, so text inserted before it is on the other side
+    Before(CompactPosID),
+    /// Just after the atom
+    After(CompactPosID),
+    DocEnd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarkOp {
+    Add(Mark),
+    Remove(MarkType),
+}
+
+#[derive(Debug, Clone)]
+pub struct MarkSignal {
+    // (lamport, site) -> total order for resolving concurrent marks
+    pub(crate) id: (u64, SDIS),
+    pub(crate) start: Anchor,
+    pub(crate) end: Anchor,
+    pub(crate) op: MarkOp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormattedRun {
+    pub text: String,
+    /// Sorted by mark type
+    pub marks: Vec<Mark>,
+}
+
+impl Mark {
+    pub fn mark_type(&self) -> MarkType {
+        match self {
+            Mark::Bold => MarkType::Bold,
+            Mark::Italic => MarkType::Italic,
+            Mark::Link(_) => MarkType::Link,
+        }
+    }
+}
+
+impl MarkType {
+    // Typing at the end of a bold span continues it, typing at the end of a link does not
+    fn expands_at_end(&self) -> bool {
+        !matches!(self, MarkType::Link)
+    }
+}
+
+impl MarkOp {
+    fn mark_type(&self) -> MarkType {
+        match self {
+            MarkOp::Add(mark) => mark.mark_type(),
+            MarkOp::Remove(mark_type) => *mark_type,
+        }
+    }
+}
+
+impl Treedoc {
+    pub fn mark(&mut self, range: Range<usize>, mark: Mark) -> Result<MarkSignal> {
+        self.new_mark_signal(range, MarkOp::Add(mark))
+    }
+
+    pub fn unmark(&mut self, range: Range<usize>, mark_type: MarkType) -> Result<MarkSignal> {
+        self.new_mark_signal(range, MarkOp::Remove(mark_type))
+    }
+
+    /// Formatting of the visible text in `range` as runs of equally marked text
+    pub fn formatted_runs(&self, range: Range<usize>) -> Vec<FormattedRun> {
+        let end = range.end.min(self.doc_length);
+        // Resolve every op to a gap range once, oldest first so the last write wins
+        let mut resolved: Vec<(&MarkSignal, Range<usize>)> = self
+            .marks
+            .iter()
+            .map(|sig| (sig, self.resolve(&sig.start)..self.resolve(&sig.end)))
+            .collect();
+        resolved.sort_by_key(|(sig, _)| sig.id);
+
+        let mut runs: Vec<FormattedRun> = Vec::new();
+        for (idx, atom) in (range.start..end).zip(self.atoms().skip(range.start)) {
+            let mut marks: Vec<Mark> = Vec::new();
+            for (sig, _) in resolved.iter().filter(|(_, gaps)| gaps.contains(&idx)) {
+                marks.retain(|m| m.mark_type() != sig.op.mark_type());
+                if let MarkOp::Add(mark) = &sig.op {
+                    marks.push(mark.clone());
+                }
+            }
+            marks.sort();
+            match runs.last_mut() {
+                Some(run) if run.marks == marks => run.text.push(atom.to_char()),
+                _ => runs.push(FormattedRun {
+                    text: atom.to_string(),
+                    marks,
+                }),
+            }
+        }
+        runs
+    }
+
+    // False if the mark was there already
+    pub(crate) fn apply_mark(&mut self, op: MarkSignal) -> bool {
+        self.lamport = self.lamport.max(op.id.0);
+        if self.marks.iter().all(|sig| sig.id != op.id) {
+            if !self.subscribers.is_empty() {
+                let range = self.resolve(&op.start)..self.resolve(&op.end);
+                if !range.is_empty() {
+                    self.emit(Change::Formatted { range });
+                }
+            }
+            self.marks.push(op);
+            return true;
+        }
+        false
+    }
+
+    fn new_mark_signal(&mut self, range: Range<usize>, op: MarkOp) -> Result<MarkSignal> {
+        if range.is_empty() || range.end > self.doc_length {
+            return Err(Error::from(ErrorKind::InvalidInput));
+        }
+        let id_at = |idx| CompactPosID::from(&self.iter().nth(idx).unwrap());
+        let start = Anchor::Before(id_at(range.start));
+        let end = if !op.mark_type().expands_at_end() {
+            Anchor::After(id_at(range.end - 1))
+        } else if range.end == self.doc_length {
+            Anchor::DocEnd
+        } else {
+            Anchor::Before(id_at(range.end))
+        };
+        self.lamport += 1;
+        Ok(MarkSignal {
+            id: (self.lamport, self.unique_disambiguator),
+            start,
+            end,
+            op,
+        })
+    }
+
+    // Gap index of an anchor in the current visible text
+    fn resolve(&self, anchor: &Anchor) -> usize {
+        match anchor {
+            Anchor::Before(pos_id) => self.rank(&pos_id.to_pos_id()).0,
+            Anchor::After(pos_id) => match self.rank(&pos_id.to_pos_id()) {
+                (idx, Some(mini)) if mini.borrow().is_visible() => idx + 1,
+                (idx, _) => idx,
+            },
+            Anchor::DocEnd => self.doc_length,
+        }
+    }
+}
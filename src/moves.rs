@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Result},
+    ops::Range,
+};
+
+use crate::{
+    events::Change,
+    node::{SDIS, subtree_len},
+    offset::CharOffset,
+    pos_id::{CompactPosID, PosID},
+    treedoc::{InsertSignal, Signal, Treedoc, Undo},
+};
+
+/*
+Moves after Kleppmann, "Moving elements in list CRDTs": an atom keeps its
+identity, the PosID it was inserted at, and a move gives it a new slot elsewhere
+in the tree holding a copy of it. Only the slot of the latest move (by lamport
+clock, then site) shows the atom. The original and the slots of older moves stay
+behind hidden, so inserts made next to them still find their parent, and two
+concurrent moves of the same atom never duplicate it. Deletes go to the identity
+and hide the atom wherever it is shown.
+*/
+
+#[derive(Debug, Clone)]
+pub struct MoveSignal {
+    // (lamport, site) -> the latest move of an atom wins
+    pub(crate) id: (u64, SDIS),
+    /// Where the atom was inserted, which identifies it
+    pub(crate) origin: CompactPosID,
+    /// The new slot, with a copy of the atom
+    pub(crate) slot: InsertSignal,
+}
+
+// Id of a move and the path of its slot
+type Slot = ((u64, SDIS), CompactPosID);
+
+/// Finds the entries of `Treedoc::moves` by moved atom and by slot
+#[derive(Debug, Clone, Default)]
+pub struct MoveIndex {
+    // origin -> id and slot path of every move of that atom
+    of_atom: HashMap<CompactPosID, Vec<Slot>>,
+    // slot path -> origin
+    origins: HashMap<CompactPosID, CompactPosID>,
+}
+
+impl MoveIndex {
+    fn add(&mut self, op: &MoveSignal) {
+        let slot = CompactPosID::from(&op.slot.atom_path());
+        self.origins.insert(slot.clone(), op.origin.clone());
+        let moves = self.of_atom.entry(op.origin.clone()).or_default();
+        moves.push((op.id, slot));
+    }
+
+    fn remove(&mut self, op: &MoveSignal) {
+        self.origins
+            .remove(&CompactPosID::from(&op.slot.atom_path()));
+        if let Some(moves) = self.of_atom.get_mut(&op.origin) {
+            moves.retain(|(id, _)| *id != op.id);
+            if moves.is_empty() {
+                self.of_atom.remove(&op.origin);
+            }
+        }
+    }
+
+    fn of_atom(&self, origin: &CompactPosID) -> &[Slot] {
+        self.of_atom.get(origin).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl Treedoc {
+    /// Moves the atom at char index `from` to the gap `to`, both counted in the
+    /// current text
+    pub fn move_atom(&mut self, from: usize, to: usize) -> Result<MoveSignal> {
+        if from >= self.doc_length || to > self.doc_length {
+            return Err(Error::from(ErrorKind::InvalidInput));
+        }
+        let (shown, atom) = self.iter_with_ids().nth(from).unwrap();
+        let origin = CompactPosID::from(&self.origin_of(&shown));
+        let slot = self.insert(CharOffset(to), atom)?;
+        self.lamport += 1;
+        Ok(MoveSignal {
+            id: (self.lamport, self.unique_disambiguator),
+            origin,
+            slot,
+        })
+    }
+
+    /// Moves the chars in `range` to the gap `to` as one transaction; `to` may
+    /// not be inside the range
+    pub fn move_range(&mut self, range: Range<usize>, to: usize) -> Result<Signal> {
+        self.transaction(|tx| tx.move_range(range, to))
+    }
+
+    pub(crate) fn apply_move(&mut self, op: MoveSignal, undo: &mut Vec<Undo>) -> Result<()> {
+        self.lamport = self.lamport.max(op.id.0);
+        if self
+            .move_index
+            .of_atom(&op.origin)
+            .iter()
+            .any(|(id, _)| *id == op.id)
+        {
+            return Ok(());
+        }
+        let origin = op.origin.to_pos_id();
+        let report = !self.subscribers.is_empty();
+        let before = if report { self.pos_of(&origin) } else { None };
+        let ch = op.slot.atom.to_char();
+        let slot = self.insert_mini(op.slot.clone(), undo)?;
+        // Hidden until settled, in case it loses
+        if let Some(mini) = self.mini_at(&slot) {
+            mini.borrow_mut().moved = true;
+        }
+        undo.push(Undo::Move(op.id));
+        self.move_index.add(&op);
+        self.moves.push(op);
+        self.settle_moves(&origin);
+
+        let after = if report { self.pos_of(&origin) } else { None };
+        if before != after {
+            if let Some(index) = before {
+                self.emit(Change::Deleted { index, len: 1 });
+            }
+            if let Some(index) = after {
+                let text = ch.to_string();
+                self.emit(Change::Inserted { index, text });
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn unapply_move(&mut self, id: (u64, SDIS)) {
+        // Undone in reverse, so usually the last one
+        if let Some(idx) = self.moves.iter().rposition(|m| m.id == id) {
+            let op = self.moves.remove(idx);
+            self.move_index.remove(&op);
+            self.settle_moves(&op.origin.to_pos_id());
+        }
+    }
+
+    // Shows the atom at the slot of its latest move, or where it was inserted
+    // if it never moved. Slots share the tombstone of the original.
+    pub(crate) fn settle_moves(&mut self, origin: &PosID) {
+        let compact = CompactPosID::from(origin);
+        let slots: Vec<_> = self
+            .move_index
+            .of_atom(&compact)
+            .iter()
+            .map(|(id, path)| (*id, path.to_pos_id()))
+            .collect();
+        for (_, path) in &slots {
+            self.own_path(&path.0);
+        }
+        self.own_path(&origin.0);
+        let original = self.mini_at(origin);
+        let tombstone = original.as_ref().map(|m| m.borrow().tombstone);
+        let winner = self.latest_move(&compact).map(|(id, _)| *id);
+        let mut changed = Vec::new();
+        for (id, path) in slots {
+            if let Some(mini) = self.mini_at(&path) {
+                let mut mini = mini.borrow_mut();
+                mini.moved = Some(id) != winner;
+                if let Some(tombstone) = tombstone {
+                    mini.tombstone = tombstone;
+                }
+                changed.push(path);
+            }
+        }
+        if let Some(mini) = original {
+            mini.borrow_mut().moved = winner.is_some();
+            changed.push(origin.clone());
+        }
+        for path in changed {
+            self.update_on_path(&path.0);
+        }
+        self.doc_length = subtree_len(&self.root).chars;
+    }
+
+    // Identity of the atom at `pos_id`, which may be the slot of a move
+    pub(crate) fn origin_of(&self, pos_id: &PosID) -> PosID {
+        match self.move_index.origins.get(&CompactPosID::from(pos_id)) {
+            Some(origin) => origin.to_pos_id(),
+            None => pos_id.clone(),
+        }
+    }
+
+    // Where the atom identified by `pos_id` is shown, if it is
+    pub(crate) fn current_path(&self, pos_id: &PosID) -> PosID {
+        match self.latest_move(&CompactPosID::from(pos_id)) {
+            Some((_, slot)) => slot.to_pos_id(),
+            None => pos_id.clone(),
+        }
+    }
+
+    fn latest_move(&self, origin: &CompactPosID) -> Option<&Slot> {
+        self.move_index
+            .of_atom(origin)
+            .iter()
+            .max_by_key(|(id, _)| *id)
+    }
+}
@@ -1,6 +1,16 @@
-use std::{cell::RefCell, io::Error, rc::Rc};
+use std::{cell::RefCell, collections::BTreeMap, fmt, io::Error, rc::Rc};
 
-pub type Atom = char;
+use crate::{encoding::to_bytes, offset::TextLength};
+
+/// Stand-in for embeds wherever the document is rendered or measured as text
+pub const EMBED_CHAR: char = '\u{FFFC}';
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Atom {
+    Char(char),
+    /// A non-text object (image, mention...) taking up a single slot
+    Embed(String, BTreeMap<String, String>),
+}
 
 // SDIS only on mininodes -> this is why PathComponents with
 // disambiguators are mininodes and ones without are major nodes
@@ -19,6 +29,10 @@ pub struct Node {
     pub children: RefCell<Vec<Rc<RefCell<Mininode>>>>,
     pub left: Option<Rc<RefCell<Node>>>,
     pub right: Option<Rc<RefCell<Node>>>,
+    /// Visible length of the whole subtree
+    pub len: TextLength,
+    /// Merkle hash of the whole subtree, tombstones included
+    pub hash: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -26,6 +40,16 @@ pub struct Mininode {
     pub disambiguator: SDIS, // SDIS
     pub atom: Atom,
     pub tombstone: bool,
+    /// The atom is shown at the slot of a move instead, see `moves`
+    pub moved: bool,
+    /// (lamport, site) of the insert that made the atom, see `blame`
+    pub inserted: (u64, SDIS),
+    /// (lamport, site) of every delete of the atom, earliest first
+    pub deleted: Vec<(u64, SDIS)>,
+    /// Visible length of left + self + right
+    pub len: TextLength,
+    /// Merkle hash of left + self + right
+    pub hash: u64,
 
     pub left: Option<Rc<RefCell<Node>>>,
     pub right: Option<Rc<RefCell<Node>>>,
@@ -37,28 +61,86 @@ pub enum AtPosition {
     Mini(Option<Rc<RefCell<Mininode>>>),
 }
 
+impl Atom {
+    /// The char the atom is rendered as, embeds become U+FFFC
+    pub fn to_char(&self) -> char {
+        match self {
+            Atom::Char(ch) => *ch,
+            Atom::Embed(..) => EMBED_CHAR,
+        }
+    }
+
+    pub fn is_embed(&self) -> bool {
+        matches!(self, Atom::Embed(..))
+    }
+
+    /// Short human readable form for debugging output
+    pub fn label(&self) -> String {
+        match self {
+            Atom::Char(ch) => format!("{ch:?}"),
+            Atom::Embed(id, _) => format!("[embed {id}]"),
+        }
+    }
+}
+
+impl From<char> for Atom {
+    fn from(ch: char) -> Self {
+        Atom::Char(ch)
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_char())
+    }
+}
+
+impl FromIterator<Atom> for String {
+    fn from_iter<T: IntoIterator<Item = Atom>>(iter: T) -> Self {
+        iter.into_iter().map(|atom| atom.to_char()).collect()
+    }
+}
+
+impl<'a> FromIterator<&'a Atom> for String {
+    fn from_iter<T: IntoIterator<Item = &'a Atom>>(iter: T) -> Self {
+        iter.into_iter().map(Atom::to_char).collect()
+    }
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Node {
     pub fn new() -> Self {
-        Self {
+        let mut node = Self {
             children: RefCell::new(Vec::new()),
             left: None,
             right: None,
-        }
+            len: TextLength::default(),
+            hash: 0,
+        };
+        node.update_hash();
+        node
     }
 
     pub fn new_with_mini(atom: Atom, dis: SDIS) -> Self {
-        let mini = Rc::new(RefCell::new(Mininode {
-            atom: atom,
-            disambiguator: dis,
-            left: None,
-            right: None,
-            tombstone: false,
-        }));
-        Self {
-            children: RefCell::new(vec![mini]),
+        Self::from_mini(Mininode::new_with_atom(atom, dis))
+    }
+
+    pub fn from_mini(mini: Mininode) -> Self {
+        let len = mini.len;
+        let mut node = Self {
+            children: RefCell::new(vec![Rc::new(RefCell::new(mini))]),
             left: None,
             right: None,
-        }
+            len,
+            hash: 0,
+        };
+        node.update_hash();
+        node
     }
 
     pub fn add_mini(&self, mini: Mininode) {
@@ -86,17 +168,83 @@ impl Node {
     pub fn add_right(&mut self, node: Node) {
         self.right = Some(Rc::new(RefCell::new(node)))
     }
+
+    /// Recomputes `len` from the children, which must be up to date
+    pub fn update_len(&mut self) {
+        let mut len = subtree_len(&self.left) + subtree_len(&self.right);
+        for mini in self.children.borrow().iter() {
+            len += mini.borrow().len;
+        }
+        self.len = len;
+    }
+
+    /// Recomputes `hash` from the children, which must be up to date
+    pub fn update_hash(&mut self) {
+        let mut hasher = Fnv::new();
+        hasher.write(b"n");
+        hasher.write_u64(subtree_hash(&self.left));
+        for mini in self.children.borrow().iter() {
+            hasher.write_u64(mini.borrow().hash);
+        }
+        hasher.write_u64(subtree_hash(&self.right));
+        self.hash = hasher.finish();
+    }
 }
 
 impl Mininode {
     pub fn new_with_atom(atom: Atom, dis: SDIS) -> Self {
-        Mininode {
-            atom: atom,
+        let mut mini = Mininode {
+            len: TextLength::of(&atom),
+            hash: 0,
+            atom,
             disambiguator: dis,
             left: None,
             right: None,
             tombstone: false,
+            moved: false,
+            inserted: (0, dis),
+            deleted: Vec::new(),
+        };
+        mini.update_hash();
+        mini
+    }
+
+    pub fn is_visible(&self) -> bool {
+        !self.tombstone && !self.moved
+    }
+
+    /// Visible length of the atom itself
+    pub fn atom_len(&self) -> TextLength {
+        if self.is_visible() {
+            TextLength::of(&self.atom)
+        } else {
+            TextLength::default()
+        }
+    }
+
+    /// Recomputes `len` from the children, which must be up to date
+    pub fn update_len(&mut self) {
+        self.len = subtree_len(&self.left) + self.atom_len() + subtree_len(&self.right);
+    }
+
+    /// Recomputes `hash` from the children, which must be up to date. The
+    /// disambiguator and the position in the tree make up the PosID, so equal
+    /// hashes mean equal PosIDs, atoms, tombstones, moves and edit stamps.
+    pub fn update_hash(&mut self) {
+        let mut hasher = Fnv::new();
+        hasher.write(b"m");
+        hasher.write_u64(self.disambiguator);
+        hasher.write(&to_bytes(&self.atom));
+        hasher.write(&[self.tombstone as u8, self.moved as u8]);
+        // Deletes are kept sorted, so replicas that applied the same ones agree
+        hasher.write_u64(self.deleted.len() as u64);
+        for (lamport, site) in [self.inserted].iter().chain(&self.deleted) {
+            hasher.write_u64(*lamport);
+            hasher.write_u64(*site);
         }
+        hasher.write_u64(subtree_hash(&self.left));
+        hasher.write_u64(subtree_hash(&self.right));
+        self.hash = hasher.finish();
     }
 
     pub fn add_left(&mut self, node: Node) {
@@ -107,5 +255,39 @@ impl Mininode {
         self.right = Some(Rc::new(RefCell::new(node)))
     }
 
-    pub fn add_mini(&self, mini: Mininode) {}
+    pub fn add_mini(&self, _mini: Mininode) {}
+}
+
+pub fn subtree_len(node: &Option<Rc<RefCell<Node>>>) -> TextLength {
+    node.as_ref().map(|n| n.borrow().len).unwrap_or_default()
+}
+
+/// Hash of an absent subtree is 0
+pub fn subtree_hash(node: &Option<Rc<RefCell<Node>>>) -> u64 {
+    node.as_ref().map(|n| n.borrow().hash).unwrap_or_default()
+}
+
+// FNV-1a: replicas on other machines and builds must agree on hashes, which
+// std's hashers don't promise. Not meant to stand up to crafted collisions.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_u64(&mut self, v: u64) {
+        self.write(&v.to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
 }
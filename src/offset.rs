@@ -0,0 +1,109 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    ops::{Add, AddAssign},
+};
+
+use crate::{node::Atom, treedoc::Treedoc};
+
+// Offsets point to "gaps" between atoms, counted in the unit of the wrapper.
+// LSP and browsers speak UTF-16 code units, most Rust tooling speaks UTF-8
+// bytes and the Treedoc itself counts atoms (chars).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CharOffset(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Utf8Offset(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Utf16Offset(pub usize);
+
+/// Visible length of a subtree in every unit we hand out offsets in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextLength {
+    pub chars: usize,
+    pub utf8: usize,
+    pub utf16: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Unit {
+    Char,
+    Utf8,
+    Utf16,
+}
+
+pub trait TextOffset {
+    /// Resolves the offset into a gap index counted in chars
+    fn to_char_index(&self, td: &Treedoc) -> Result<usize>;
+
+    /// Resolves the offset into the index of the char that starts at it
+    fn to_delete_index(&self, td: &Treedoc) -> Result<usize> {
+        let idx = self.to_char_index(td)?;
+        if idx >= td.doc_length {
+            return Err(Error::from(ErrorKind::InvalidInput));
+        }
+        Ok(idx)
+    }
+}
+
+impl TextLength {
+    // Embeds count as one char and are measured as U+FFFC in the other units
+    pub fn of(atom: &Atom) -> Self {
+        let ch = atom.to_char();
+        Self {
+            chars: 1,
+            utf8: ch.len_utf8(),
+            utf16: ch.len_utf16(),
+        }
+    }
+
+    pub(crate) fn get(&self, unit: Unit) -> usize {
+        match unit {
+            Unit::Char => self.chars,
+            Unit::Utf8 => self.utf8,
+            Unit::Utf16 => self.utf16,
+        }
+    }
+}
+
+impl Add for TextLength {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            chars: self.chars + rhs.chars,
+            utf8: self.utf8 + rhs.utf8,
+            utf16: self.utf16 + rhs.utf16,
+        }
+    }
+}
+
+impl AddAssign for TextLength {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+// Plain usize counts chars, like `CharOffset`
+impl TextOffset for usize {
+    fn to_char_index(&self, _td: &Treedoc) -> Result<usize> {
+        Ok(*self)
+    }
+}
+
+impl TextOffset for CharOffset {
+    fn to_char_index(&self, _td: &Treedoc) -> Result<usize> {
+        Ok(self.0)
+    }
+}
+
+impl TextOffset for Utf8Offset {
+    fn to_char_index(&self, td: &Treedoc) -> Result<usize> {
+        td.unit_to_char(self.0, Unit::Utf8)
+    }
+}
+
+impl TextOffset for Utf16Offset {
+    fn to_char_index(&self, td: &Treedoc) -> Result<usize> {
+        td.unit_to_char(self.0, Unit::Utf16)
+    }
+}
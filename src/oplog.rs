@@ -0,0 +1,160 @@
+use std::{collections::BTreeMap, io::ErrorKind};
+
+use crate::{
+    node::SDIS,
+    treedoc::{Signal, Treedoc},
+};
+
+/// Identifies an operation by the site that made it and its sequence number there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OpId {
+    pub site: SDIS,
+    pub seq: u64,
+}
+
+/// Highest sequence number seen from each site. Ops of a site are delivered in
+/// order, so this summarizes everything a replica has seen.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionVector(pub BTreeMap<SDIS, u64>);
+
+/// Every operation a replica has applied, in the order it applied them
+#[derive(Debug, Clone, Default)]
+pub struct OpLog {
+    ops: Vec<(OpId, Signal)>,
+    version: VersionVector,
+}
+
+impl VersionVector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, site: SDIS) -> u64 {
+        self.0.get(&site).copied().unwrap_or(0)
+    }
+
+    pub fn contains(&self, id: &OpId) -> bool {
+        self.get(id.site) >= id.seq
+    }
+
+    pub fn observe(&mut self, id: &OpId) {
+        let seq = self.0.entry(id.site).or_insert(0);
+        *seq = (*seq).max(id.seq);
+    }
+
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (site, seq) in &other.0 {
+            self.observe(&OpId {
+                site: *site,
+                seq: *seq,
+            });
+        }
+    }
+
+    /// Whether everything in `other` has been seen here as well
+    pub fn dominates(&self, other: &VersionVector) -> bool {
+        other.0.iter().all(|(site, seq)| self.get(*site) >= *seq)
+    }
+}
+
+impl OpLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn version(&self) -> &VersionVector {
+        &self.version
+    }
+
+    pub fn ops(&self) -> &[(OpId, Signal)] {
+        &self.ops
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn contains(&self, id: &OpId) -> bool {
+        self.version.contains(id)
+    }
+
+    /// Whether `id` is the next op expected from its site
+    pub fn is_next(&self, id: &OpId) -> bool {
+        id.seq == self.version.get(id.site) + 1
+    }
+
+    /// Logs an op made by `site` on this replica under its next sequence number
+    pub fn push_local(&mut self, site: SDIS, sig: Signal) -> OpId {
+        let id = OpId {
+            site,
+            seq: self.version.get(site) + 1,
+        };
+        self.push(id, sig);
+        id
+    }
+
+    /// Logs an applied op. Returns false if it was already known.
+    pub fn push(&mut self, id: OpId, sig: Signal) -> bool {
+        if self.contains(&id) {
+            return false;
+        }
+        self.version.observe(&id);
+        self.ops.push((id, sig));
+        true
+    }
+
+    /// Ops a replica at `remote` has not seen yet, in an order it can apply them
+    pub fn missing_for(&self, remote: &VersionVector) -> Vec<(OpId, Signal)> {
+        self.ops
+            .iter()
+            .filter(|(id, _)| !remote.contains(id))
+            .cloned()
+            .collect()
+    }
+
+    /// Applies buffered remote ops to `doc` and logs them, as soon as everything
+    /// before them from the same site is in and their parent exists. `on_applied`
+    /// sees each one along with its tag (e.g. the connection it came from).
+    /// Ops the document rejects for any other reason are dropped without being
+    /// logged, so a good copy can still arrive from elsewhere; the tags they
+    /// came with are returned.
+    pub(crate) fn deliver<T>(
+        &mut self,
+        doc: &mut Treedoc,
+        pending: &mut Vec<(OpId, Signal, T)>,
+        mut on_applied: impl FnMut(OpId, &Signal, &T),
+    ) -> Vec<T> {
+        let mut rejected = Vec::new();
+        loop {
+            let mut progress = false;
+            let mut idx = 0;
+            while idx < pending.len() {
+                if !self.is_next(&pending[idx].0) {
+                    idx += 1;
+                    continue;
+                }
+                match doc.apply(pending[idx].1.clone()) {
+                    Ok(()) => {
+                        let (op_id, sig, tag) = pending.remove(idx);
+                        on_applied(op_id, &sig, &tag);
+                        self.push(op_id, sig);
+                        progress = true;
+                    }
+                    // Parent not here yet
+                    Err(e) if e.kind() == ErrorKind::NotFound => idx += 1,
+                    Err(_) => {
+                        let (_, _, tag) = pending.remove(idx);
+                        rejected.push(tag);
+                    }
+                }
+            }
+            if !progress {
+                return rejected;
+            }
+        }
+    }
+}
@@ -1,16 +1,31 @@
-use std::{cmp::Ordering, usize};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt,
+    io::{Error, ErrorKind, Result},
+    rc::Rc,
+};
 
-use crate::node::SDIS;
+use crate::{
+    encoding::{Decode, Encode},
+    node::SDIS,
+};
 
 // PathComponent(1, None) -> to major on the right
 // PathComponent(0, None) -> to major on the left
 // PathComponent(0, Some(dis)) -> get the mininode from within (distinct step)
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct PathComponent(pub usize, pub Option<SDIS>);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PosID(pub Vec<PathComponent>);
 
+impl Default for PosID {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PosID {
     pub fn new() -> Self {
         Self(Vec::new())
@@ -22,6 +37,11 @@ impl PosID {
         pos
     }
 
+    fn ranks(&self) -> impl Iterator<Item = (u8, usize, SDIS)> + '_ {
+        let end = std::iter::once(END_RANK);
+        self.0.iter().map(PathComponent::rank).chain(end)
+    }
+
     pub fn strip_to_major(&self) -> Self {
         let mut temp = self.clone();
         while let Some(last_component) = temp.0.last() {
@@ -35,31 +55,282 @@ impl PosID {
     }
 }
 
+// Where a path ends sorts after its left subtree and before everything else
+// below it, so comparing ranks lexicographically is the infix order of the tree
+const END_RANK: (u8, usize, SDIS) = (1, 0, 0);
+
+impl PathComponent {
+    fn rank(&self) -> (u8, usize, SDIS) {
+        match *self {
+            PathComponent(0, None) => (0, 0, 0),
+            PathComponent(dir, Some(dis)) => (2, dir, dis),
+            PathComponent(dir, None) => (3, dir, 0),
+        }
+    }
+}
+
+impl Ord for PathComponent {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
 impl PartialOrd for PathComponent {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match (self, other) {
-            (PathComponent(a, None), PathComponent(b, None)) => a.partial_cmp(b),
-            (PathComponent(a, Some(dis_a)), PathComponent(b, Some(dis_b))) => a
-                .partial_cmp(b)
-                .filter(|o| o.is_ne())
-                .or_else(|| dis_a.partial_cmp(dis_b)),
-            (PathComponent(0, None), PathComponent(_, Some(_))) => Some(Ordering::Less),
-            (PathComponent(_, Some(_)), PathComponent(0, None)) => Some(Ordering::Greater),
-            (PathComponent(_, Some(_)), PathComponent(1, None)) => Some(Ordering::Less),
-            (PathComponent(1, None), PathComponent(_, Some(_))) => Some(Ordering::Greater),
-            (PathComponent(a, _), PathComponent(b, _)) => a.partial_cmp(b), // Def
-        }
+        Some(self.cmp(other))
+    }
+}
+
+/// Document order: left subtree, the node's own atoms, right subtree
+impl Ord for PosID {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.ranks().cmp(other.ranks())
     }
 }
 
 impl PartialOrd for PosID {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.0.partial_cmp(&other.0)
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Major steps as 0/1 and mininodes as (dis), e.g. 1(1)0(2)
+impl fmt::Display for PosID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for comp in &self.0 {
+            match comp {
+                PathComponent(_, Some(dis)) => write!(f, "({dis})")?,
+                PathComponent(dir, None) => write!(f, "{dir}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Small copyable stand-in for a PosID, handed out by a `PosIDInterner`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InternedPosID(u32);
+
+/// Keeps one compact copy of every PosID it sees, so per-character data
+/// (cursors, comments...) can be keyed on 4 byte handles instead of whole paths
+#[derive(Debug, Default)]
+pub struct PosIDInterner {
+    ids: Vec<Rc<CompactPosID>>,
+    handles: HashMap<Rc<CompactPosID>, InternedPosID>,
+}
+
+impl PosIDInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, pos_id: &PosID) -> InternedPosID {
+        let pos_id = CompactPosID::from(pos_id);
+        if let Some(handle) = self.handles.get(&pos_id) {
+            return *handle;
+        }
+        let handle = InternedPosID(self.ids.len() as u32);
+        let pos_id = Rc::new(pos_id);
+        self.ids.push(pos_id.clone());
+        self.handles.insert(pos_id, handle);
+        handle
+    }
+
+    /// The handle of an already interned PosID
+    pub fn get(&self, pos_id: &PosID) -> Option<InternedPosID> {
+        self.handles.get(&CompactPosID::from(pos_id)).copied()
+    }
+
+    /// The PosID behind a handle this interner handed out
+    pub fn resolve(&self, handle: InternedPosID) -> Option<PosID> {
+        self.ids.get(handle.0 as usize).map(|id| id.to_pos_id())
+    }
+
+    /// Document order of two handles
+    pub fn cmp(&self, a: InternedPosID, b: InternedPosID) -> Option<Ordering> {
+        let id = |h: InternedPosID| self.ids.get(h.0 as usize);
+        Some(id(a)?.cmp(id(b)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
     }
 }
 
-impl PartialEq for PosID {
-    fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+/*
+A PosID packed for storage and the wire. Paths are mostly left/right steps with
+a mininode step here and there, so the bytes are a list of varint tokens, the
+kind in the low two bits:
+
+- RUN, `len << 2`: `len` left/right steps, a bit each (1 = right) in the
+  `ceil(len / 8)` bytes that follow, lowest bit first
+- MINI, `dis << 2 | 1`: a mininode step
+- MAJOR, `2` then the direction as a varint: any other major step, e.g. the
+  end sentinel
+- OTHER_MINI, `3` then direction and disambiguator: mininode steps that don't
+  fit the short form
+
+Runs are as long as they can be and the short forms are used whenever they fit,
+so every PosID has exactly one encoding and the bytes can be compared for
+equality and hashed directly.
+*/
+
+const RUN: u64 = 0;
+const MINI: u64 = 1;
+const MAJOR: u64 = 2;
+const OTHER_MINI: u64 = 3;
+
+/// Bit-packed PosID, see above. Orders like the PosID it stands for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CompactPosID(Box<[u8]>);
+
+impl CompactPosID {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Takes bytes from `as_bytes`; anything else is InvalidData
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut comps = Components::new(bytes);
+        let mut pos_id = PosID::new();
+        while let Some(comp) = comps.try_next()? {
+            pos_id.0.push(comp);
+        }
+        // Only the canonical encoding, so equal PosIDs have equal bytes
+        let compact = CompactPosID::from(&pos_id);
+        if *compact.0 != *bytes {
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+        Ok(compact)
+    }
+
+    pub fn to_pos_id(&self) -> PosID {
+        PosID(self.components().collect())
+    }
+
+    pub fn components(&self) -> impl Iterator<Item = PathComponent> + '_ {
+        let mut comps = Components::new(&self.0);
+        // Checked when the bytes were made
+        std::iter::from_fn(move || comps.try_next().ok().flatten())
+    }
+}
+
+impl From<&PosID> for CompactPosID {
+    fn from(pos_id: &PosID) -> Self {
+        let mut buf = Vec::new();
+        let mut run: Vec<bool> = Vec::new();
+        let flush = |run: &mut Vec<bool>, buf: &mut Vec<u8>| {
+            if run.is_empty() {
+                return;
+            }
+            ((run.len() as u64) << 2 | RUN).encode(buf);
+            for chunk in run.chunks(8) {
+                let byte = chunk
+                    .iter()
+                    .rev()
+                    .fold(0, |byte, right| byte << 1 | *right as u8);
+                buf.push(byte);
+            }
+            run.clear();
+        };
+        for comp in &pos_id.0 {
+            match *comp {
+                PathComponent(dir @ (0 | 1), None) => run.push(dir == 1),
+                PathComponent(dir, None) => {
+                    flush(&mut run, &mut buf);
+                    MAJOR.encode(&mut buf);
+                    dir.encode(&mut buf);
+                }
+                PathComponent(0, Some(dis)) if dis < 1 << 62 => {
+                    flush(&mut run, &mut buf);
+                    (dis << 2 | MINI).encode(&mut buf);
+                }
+                PathComponent(dir, Some(dis)) => {
+                    flush(&mut run, &mut buf);
+                    OTHER_MINI.encode(&mut buf);
+                    dir.encode(&mut buf);
+                    dis.encode(&mut buf);
+                }
+            }
+        }
+        flush(&mut run, &mut buf);
+        CompactPosID(buf.into_boxed_slice())
+    }
+}
+
+impl From<&CompactPosID> for PosID {
+    fn from(compact: &CompactPosID) -> Self {
+        compact.to_pos_id()
+    }
+}
+
+impl Ord for CompactPosID {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let end = || std::iter::once(END_RANK);
+        let ours = self.components().map(|c| c.rank()).chain(end());
+        ours.cmp(other.components().map(|c| c.rank()).chain(end()))
+    }
+}
+
+impl PartialOrd for CompactPosID {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reads the tokens of a CompactPosID back into components
+struct Components<'a> {
+    buf: &'a [u8],
+    // Bits of the current run and how many of them are left
+    bits: &'a [u8],
+    next_bit: usize,
+    left: usize,
+}
+
+impl<'a> Components<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            bits: &[],
+            next_bit: 0,
+            left: 0,
+        }
+    }
+
+    fn try_next(&mut self) -> Result<Option<PathComponent>> {
+        if self.left > 0 {
+            let right = self.bits[self.next_bit / 8] >> (self.next_bit % 8) & 1;
+            self.next_bit += 1;
+            self.left -= 1;
+            return Ok(Some(PathComponent(right as usize, None)));
+        }
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let token = u64::decode(&mut self.buf)?;
+        let invalid = || Error::from(ErrorKind::InvalidData);
+        match token & 3 {
+            RUN => {
+                let len = usize::try_from(token >> 2).map_err(|_| invalid())?;
+                if len == 0 || self.buf.len() < len.div_ceil(8) {
+                    return Err(invalid());
+                }
+                (self.bits, self.buf) = self.buf.split_at(len.div_ceil(8));
+                self.next_bit = 0;
+                self.left = len;
+                self.try_next()
+            }
+            MINI => Ok(Some(PathComponent(0, Some(token >> 2)))),
+            MAJOR if token == MAJOR => Ok(Some(PathComponent(usize::decode(&mut self.buf)?, None))),
+            OTHER_MINI if token == OTHER_MINI => {
+                let dir = usize::decode(&mut self.buf)?;
+                Ok(Some(PathComponent(dir, Some(u64::decode(&mut self.buf)?))))
+            }
+            _ => Err(invalid()),
+        }
     }
 }
@@ -0,0 +1,647 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Error, ErrorKind, Result},
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    encoding::{Decode, Encode, from_bytes, read_u8, to_bytes},
+    node::SDIS,
+    sync::{Event, Framing, HANDSHAKE_TIMEOUT, Net, read_frame, write_frame},
+    treedoc::{Signal, Treedoc},
+};
+
+/*
+Hub-and-spoke alternative to the peer sync: one server hosts many documents
+("rooms") and is the only one ordering ops. A client joins a single room, gets
+its own site id plus the room's latest snapshot and the ops after it, then submits
+its edits. The server applies each submitted op, numbers it, acks it to the
+sender and forwards it to the rest of the room.
+
+Clients can leave a room and join another one over the same connection, or ask
+for a fresh copy of the room's state. With the websocket feature the server also
+accepts browsers; each binary WebSocket message carries what a TCP frame would.
+
+With a data directory every room is kept in `<room>.snap` (snapshot and the next
+free site id) plus `<room>.log`, the frames appended since that snapshot.
+*/
+
+/// Ops kept after a room's snapshot before a new one is taken
+pub const DEFAULT_MAX_RECENT: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub enum ClientMessage {
+    Join {
+        room: String,
+    },
+    Submit(Signal),
+    /// Leaves the room, the connection stays open to join another one
+    Leave,
+    /// Asks for a fresh Welcome, e.g. after a rejected edit
+    Resync,
+}
+
+#[derive(Debug, Clone)]
+pub enum ServerMessage {
+    /// The room's state: ops `base + 1..` follow the snapshot taken at `base`
+    Welcome {
+        site: SDIS,
+        base: u64,
+        snapshot: Vec<Signal>,
+        ops: Vec<Signal>,
+    },
+    Op(u64, Signal),
+    /// The oldest unacknowledged submit was ordered at this sequence number
+    Ack(u64),
+    /// The oldest unacknowledged submit (or the join) was refused
+    Rejected,
+}
+
+// Entries of a room's log file
+enum Record {
+    Joined(SDIS),
+    Op(u64, Signal),
+}
+
+struct Room {
+    doc: Treedoc,
+    next_site: SDIS,
+    base: u64,
+    snapshot: Vec<Signal>,
+    recent: Vec<Signal>,
+    clients: Vec<usize>,
+    log: Option<File>,
+}
+
+pub struct RelayServer {
+    pub max_recent: usize,
+    /// Time a WebSocket client gets for its upgrade request, for the
+    /// listeners started by later `listen_ws` calls
+    pub handshake_timeout: Duration,
+    dir: Option<PathBuf>,
+    rooms: HashMap<String, Room>,
+    // Room and site of each joined connection
+    members: HashMap<usize, (String, SDIS)>,
+    net: Net,
+    addr: SocketAddr,
+}
+
+/// A connection to one room of a relay server
+pub struct RelayClient {
+    pub doc: Treedoc,
+    site: SDIS,
+    seq: u64,
+    unacked: usize,
+    joined: bool,
+    // Joins sent whose answer hasn't arrived, only the last one counts
+    joins: usize,
+    net: Net,
+}
+
+impl Encode for ClientMessage {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            ClientMessage::Join { room } => {
+                buf.push(0);
+                room.encode(buf);
+            }
+            ClientMessage::Submit(sig) => {
+                buf.push(1);
+                sig.encode(buf);
+            }
+            ClientMessage::Leave => buf.push(2),
+            ClientMessage::Resync => buf.push(3),
+        }
+    }
+}
+
+impl Decode for ClientMessage {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match read_u8(buf)? {
+            0 => Ok(ClientMessage::Join {
+                room: String::decode(buf)?,
+            }),
+            1 => Ok(ClientMessage::Submit(Signal::decode(buf)?)),
+            2 => Ok(ClientMessage::Leave),
+            3 => Ok(ClientMessage::Resync),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+impl Encode for ServerMessage {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            ServerMessage::Welcome {
+                site,
+                base,
+                snapshot,
+                ops,
+            } => {
+                buf.push(0);
+                site.encode(buf);
+                base.encode(buf);
+                snapshot.encode(buf);
+                ops.encode(buf);
+            }
+            ServerMessage::Op(seq, sig) => {
+                buf.push(1);
+                seq.encode(buf);
+                sig.encode(buf);
+            }
+            ServerMessage::Ack(seq) => {
+                buf.push(2);
+                seq.encode(buf);
+            }
+            ServerMessage::Rejected => buf.push(3),
+        }
+    }
+}
+
+impl Decode for ServerMessage {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match read_u8(buf)? {
+            0 => Ok(ServerMessage::Welcome {
+                site: u64::decode(buf)?,
+                base: u64::decode(buf)?,
+                snapshot: Vec::decode(buf)?,
+                ops: Vec::decode(buf)?,
+            }),
+            1 => Ok(ServerMessage::Op(u64::decode(buf)?, Signal::decode(buf)?)),
+            2 => Ok(ServerMessage::Ack(u64::decode(buf)?)),
+            3 => Ok(ServerMessage::Rejected),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+impl Encode for Record {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Record::Joined(site) => {
+                buf.push(0);
+                site.encode(buf);
+            }
+            Record::Op(seq, sig) => {
+                buf.push(1);
+                seq.encode(buf);
+                sig.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Record {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match read_u8(buf)? {
+            0 => Ok(Record::Joined(u64::decode(buf)?)),
+            1 => Ok(Record::Op(u64::decode(buf)?, Signal::decode(buf)?)),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+/// Room ids double as file names, so only ASCII letters, digits, `-` and `_`
+pub fn is_valid_room(room: &str) -> bool {
+    !room.is_empty()
+        && room.len() <= 64
+        && room
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+impl Room {
+    fn new() -> Self {
+        Self {
+            doc: Treedoc::default(),
+            next_site: 1,
+            base: 0,
+            snapshot: Vec::new(),
+            recent: Vec::new(),
+            clients: Vec::new(),
+            log: None,
+        }
+    }
+
+    fn seq(&self) -> u64 {
+        self.base + self.recent.len() as u64
+    }
+
+    fn welcome(&self, site: SDIS) -> ServerMessage {
+        ServerMessage::Welcome {
+            site,
+            base: self.base,
+            snapshot: self.snapshot.clone(),
+            ops: self.recent.clone(),
+        }
+    }
+
+    // Restores the room from its files, missing files mean a new room
+    fn load(dir: &Path, name: &str) -> Result<Self> {
+        let mut room = Room::new();
+        match fs::read(dir.join(format!("{name}.snap"))) {
+            Ok(bytes) => {
+                let (next_site, (base, snapshot)) =
+                    from_bytes::<(u64, (u64, Vec<Signal>))>(&bytes)?;
+                for sig in snapshot.iter().cloned() {
+                    room.doc.apply(sig)?;
+                }
+                room.next_site = next_site;
+                room.base = base;
+                room.snapshot = snapshot;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let log_path = dir.join(format!("{name}.log"));
+        let mut good_len = 0;
+        if let Ok(file) = File::open(&log_path) {
+            let mut reader = BufReader::new(file);
+            while let Ok(frame) = read_frame(&mut reader) {
+                good_len += 4 + frame.len() as u64;
+                match from_bytes(&frame)? {
+                    Record::Joined(site) => room.next_site = room.next_site.max(site + 1),
+                    // Ops from before a snapshot whose log was not truncated yet
+                    Record::Op(seq, _) if seq <= room.seq() => {}
+                    Record::Op(_, sig) => {
+                        room.doc.apply(sig.clone())?;
+                        room.recent.push(sig);
+                    }
+                }
+            }
+        }
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path)?;
+        // A torn frame at the end is a write cut short by a crash, new frames go in its place
+        log.set_len(good_len)?;
+        room.log = Some(log);
+        Ok(room)
+    }
+
+    fn append(&mut self, name: &str, record: &Record) {
+        if let Some(log) = &mut self.log
+            && let Err(e) = write_frame(log, &to_bytes(record))
+        {
+            self.stop_saving(name, e);
+        }
+    }
+
+    // Takes a new snapshot and starts an empty log
+    fn compact(&mut self, dir: &Option<PathBuf>, name: &str) {
+        self.snapshot = self.doc.snapshot();
+        self.base = self.seq();
+        self.recent.clear();
+        if let Some(dir) = dir
+            && self.log.is_some()
+            && let Err(e) = self.save(dir, name)
+        {
+            self.stop_saving(name, e);
+        }
+    }
+
+    fn save(&mut self, dir: &Path, name: &str) -> Result<()> {
+        let path = dir.join(format!("{name}.snap"));
+        let tmp = dir.join(format!("{name}.snap.tmp"));
+        let state = (self.next_site, (self.base, self.snapshot.clone()));
+        fs::write(&tmp, to_bytes(&state))?;
+        fs::rename(tmp, path)?;
+        self.log = Some(File::create(dir.join(format!("{name}.log")))?);
+        Ok(())
+    }
+
+    // A failed write only costs this room its persistence, it goes on in memory
+    fn stop_saving(&mut self, name: &str, e: Error) {
+        eprintln!("relay: room {name} is no longer saved: {e}");
+        self.log = None;
+    }
+}
+
+impl RelayServer {
+    /// Listens on `addr`, keeping the rooms in `dir` if given
+    pub fn bind(addr: impl ToSocketAddrs, dir: Option<PathBuf>) -> Result<Self> {
+        if let Some(dir) = &dir {
+            fs::create_dir_all(dir)?;
+        }
+        let mut net = Net::new();
+        let addr = net.listen(addr, Framing::Length)?;
+        Ok(Self {
+            max_recent: DEFAULT_MAX_RECENT,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            dir,
+            rooms: HashMap::new(),
+            members: HashMap::new(),
+            net,
+            addr,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Also accepts WebSocket clients on `addr`, returns the bound address
+    #[cfg(feature = "websocket")]
+    pub fn listen_ws(&mut self, addr: impl ToSocketAddrs) -> Result<SocketAddr> {
+        self.net.handshake_timeout = self.handshake_timeout;
+        self.net.listen(addr, Framing::WsServer)
+    }
+
+    /// The room's document, if it has been opened since the server started
+    pub fn doc(&self, room: &str) -> Option<&Treedoc> {
+        self.rooms.get(room).map(|room| &room.doc)
+    }
+
+    /// Number of ops the room has ordered so far
+    pub fn seq(&self, room: &str) -> Option<u64> {
+        self.rooms.get(room).map(Room::seq)
+    }
+
+    /// Serves clients until an error occurs
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            self.poll_timeout(Duration::from_secs(1))?;
+        }
+    }
+
+    /// Waits up to `timeout` for network activity and handles it.
+    /// Returns false if nothing arrived.
+    pub fn poll_timeout(&mut self, timeout: Duration) -> Result<bool> {
+        let Some(event) = self.net.next_timeout(timeout)? else {
+            return Ok(false);
+        };
+        self.handle(event);
+        while let Some(event) = self.net.try_next() {
+            self.handle(event);
+        }
+        Ok(true)
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            // Nothing to do until it joins a room
+            Event::Connected(_) => {}
+            Event::Closed(id) => self.leave(id),
+            Event::Frame(id, payload) => match (from_bytes(&payload), self.members.get(&id)) {
+                // A client may switch rooms before the Welcome of the last one arrives
+                (Ok(ClientMessage::Join { room }), _) => {
+                    self.leave(id);
+                    self.join(id, room);
+                }
+                (Ok(ClientMessage::Submit(sig)), Some((room, _))) => {
+                    let room = room.clone();
+                    self.submit(id, &room, sig);
+                }
+                (Ok(ClientMessage::Leave), _) => self.leave(id),
+                (Ok(ClientMessage::Resync), Some((room, site))) => {
+                    let welcome = self.rooms[room].welcome(*site);
+                    self.net.send(id, &welcome);
+                }
+                _ => self.net.close(id),
+            },
+        }
+    }
+
+    fn join(&mut self, id: usize, name: String) {
+        if !is_valid_room(&name) {
+            self.reject(id);
+            return;
+        }
+        if !self.rooms.contains_key(&name) {
+            let room = match &self.dir {
+                Some(dir) => match Room::load(dir, &name) {
+                    Ok(room) => room,
+                    // Left alone on disk, a later join tries again
+                    Err(e) => {
+                        eprintln!("relay: can't open room {name}: {e}");
+                        self.reject(id);
+                        return;
+                    }
+                },
+                None => Room::new(),
+            };
+            self.rooms.insert(name.clone(), room);
+        }
+        let room = self.rooms.get_mut(&name).unwrap();
+        let site = room.next_site;
+        room.next_site += 1;
+        room.append(&name, &Record::Joined(site));
+        room.clients.push(id);
+        let welcome = room.welcome(site);
+        self.members.insert(id, (name, site));
+        self.net.send(id, &welcome);
+    }
+
+    // Refuses a join and hangs up
+    fn reject(&mut self, id: usize) {
+        self.net.send(id, &ServerMessage::Rejected);
+        self.net.close(id);
+    }
+
+    fn leave(&mut self, id: usize) {
+        if let Some((name, _)) = self.members.remove(&id)
+            && let Some(room) = self.rooms.get_mut(&name)
+        {
+            room.clients.retain(|client| *client != id);
+        }
+    }
+
+    fn submit(&mut self, id: usize, name: &str, sig: Signal) {
+        let room = self.rooms.get_mut(name).unwrap();
+        if room.doc.apply(sig.clone()).is_err() {
+            self.net.send(id, &ServerMessage::Rejected);
+            return;
+        }
+        let seq = room.seq() + 1;
+        room.append(name, &Record::Op(seq, sig.clone()));
+        room.recent.push(sig.clone());
+
+        let others: Vec<usize> = room.clients.iter().copied().filter(|c| *c != id).collect();
+        if room.recent.len() > self.max_recent {
+            room.compact(&self.dir, name);
+        }
+        self.net.send(id, &ServerMessage::Ack(seq));
+        let op = ServerMessage::Op(seq, sig);
+        for client in others {
+            self.net.send(client, &op);
+        }
+    }
+}
+
+impl RelayClient {
+    /// Asks to join `room` on the server at `addr`. The room's state arrives with
+    /// a later poll, until then the client has no site and cannot edit.
+    pub fn connect(addr: impl ToSocketAddrs, room: &str) -> Result<Self> {
+        let mut net = Net::new();
+        net.connect(addr)?;
+        Self::with_net(net, room)
+    }
+
+    /// Like `connect`, over a WebSocket to `ws://addr/`
+    #[cfg(feature = "websocket")]
+    pub fn connect_ws(addr: impl ToSocketAddrs, room: &str) -> Result<Self> {
+        let mut net = Net::new();
+        net.connect_ws(addr, "/")?;
+        Self::with_net(net, room)
+    }
+
+    fn with_net(net: Net, room: &str) -> Result<Self> {
+        let mut client = Self {
+            doc: Treedoc::default(),
+            site: 0,
+            seq: 0,
+            unacked: 0,
+            joined: false,
+            joins: 0,
+            net,
+        };
+        client.join_room(room);
+        Ok(client)
+    }
+
+    /// Connects and waits up to `timeout` for the room's state
+    pub fn join(addr: impl ToSocketAddrs, room: &str, timeout: Duration) -> Result<Self> {
+        let mut client = Self::connect(addr, room)?;
+        let deadline = Instant::now() + timeout;
+        while !client.joined {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if !client.poll_timeout(left)? && left.is_zero() {
+                return Err(Error::from(ErrorKind::TimedOut));
+            }
+        }
+        Ok(client)
+    }
+
+    pub fn is_joined(&self) -> bool {
+        self.joined
+    }
+
+    /// Leaves the current room and asks to join another one on the same connection
+    pub fn join_room(&mut self, room: &str) {
+        self.leave();
+        self.joins += 1;
+        self.net.broadcast(
+            &ClientMessage::Join {
+                room: room.to_string(),
+            },
+            None,
+        );
+    }
+
+    /// Stops receiving the room's edits. The document is kept but can no longer be edited.
+    pub fn leave(&mut self) {
+        if self.joined {
+            self.net.broadcast(&ClientMessage::Leave, None);
+            self.joined = false;
+            self.unacked = 0;
+        }
+    }
+
+    /// Asks for the room's whole state again, replacing the local document when it
+    /// arrives. Recovers from a rejected edit.
+    pub fn resync(&mut self) {
+        self.net.broadcast(&ClientMessage::Resync, None);
+    }
+
+    pub fn site(&self) -> SDIS {
+        self.site
+    }
+
+    /// Sequence number of the last op of the room seen here
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Whether the server has ordered every op submitted from here
+    pub fn is_synced(&self) -> bool {
+        self.unacked == 0
+    }
+
+    /// Applies a signal made on this replica and submits it to the room
+    pub fn apply_local(&mut self, sig: Signal) -> Result<()> {
+        if !self.joined {
+            return Err(Error::from(ErrorKind::NotConnected));
+        }
+        self.doc.apply(sig.clone())?;
+        self.unacked += 1;
+        self.net.broadcast(&ClientMessage::Submit(sig), None);
+        Ok(())
+    }
+
+    /// Handles everything received so far without blocking
+    pub fn poll(&mut self) -> Result<()> {
+        while let Some(event) = self.net.try_next() {
+            self.handle(event)?;
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for messages from the server and handles them.
+    /// Returns false if nothing arrived.
+    pub fn poll_timeout(&mut self, timeout: Duration) -> Result<bool> {
+        match self.net.next_timeout(timeout)? {
+            Some(event) => {
+                self.handle(event)?;
+                self.poll()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn handle(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::Frame(_, payload) => match from_bytes(&payload)? {
+                // Answers a join that was superseded by another one
+                ServerMessage::Welcome { .. } if self.joins > 1 => self.joins -= 1,
+                ServerMessage::Welcome {
+                    site,
+                    base,
+                    snapshot,
+                    ops,
+                } => {
+                    self.joins = 0;
+                    let mut doc = Treedoc {
+                        unique_disambiguator: site,
+                        ..Treedoc::default()
+                    };
+                    self.seq = base + ops.len() as u64;
+                    for sig in snapshot.into_iter().chain(ops) {
+                        doc.apply(sig)?;
+                    }
+                    self.doc = doc;
+                    self.site = site;
+                    self.joined = true;
+                }
+                // The join was refused
+                ServerMessage::Rejected if !self.joined => {
+                    self.joins = 0;
+                    return Err(Error::from(ErrorKind::InvalidInput));
+                }
+                // Left over from a room this client has left
+                ServerMessage::Op(..) | ServerMessage::Ack(_) if !self.joined => {}
+                ServerMessage::Op(seq, sig) => {
+                    self.doc.apply(sig)?;
+                    self.seq = seq;
+                }
+                ServerMessage::Ack(seq) => {
+                    self.unacked = self.unacked.saturating_sub(1);
+                    self.seq = seq;
+                }
+                // The local document now has an edit the room does not
+                ServerMessage::Rejected => {
+                    self.unacked = self.unacked.saturating_sub(1);
+                    return Err(Error::from(ErrorKind::InvalidData));
+                }
+            },
+            Event::Closed(_) => return Err(Error::from(ErrorKind::ConnectionAborted)),
+            Event::Connected(_) => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    cell::RefCell,
+    fs,
+    io::{Error, ErrorKind, Result, Write},
+    rc::Rc,
+};
+
+use crate::{
+    encoding::{from_bytes, to_bytes},
+    node::Node,
+    offset::CharOffset,
+    pos_id::{PathComponent, PosID},
+    treedoc::{Signal, Treedoc},
+};
+
+const HELP: &str = "\
+insert <pos> <text>  insert text at a char offset (\\n for newlines)
+delete <pos> [len]   delete len chars (default 1) starting at a char offset
+show                 print the document
+ids                  list the visible atoms with their PosIDs
+tree                 print the internal tree, tombstones included
+dot [file]           write the internal tree as Graphviz DOT
+save <file>          write the signal log of the document
+load <file>          replace the document with a saved one
+replay <file>        run the commands in a file, one per line
+quit";
+
+/// A document plus every signal applied to it, driven by text commands
+pub struct Repl {
+    pub doc: Treedoc,
+    pub log: Vec<Signal>,
+    // Set while a script runs, so it can't replay itself
+    replaying: bool,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            doc: Treedoc::default(),
+            log: Vec::new(),
+            replaying: false,
+        }
+    }
+
+    /// Runs a single command line. Returns false once the session should end.
+    pub fn execute(&mut self, line: &str, out: &mut impl Write) -> Result<bool> {
+        // Trailing spaces may be part of the inserted text
+        let line = line.trim_start().trim_end_matches(['\r', '\n']);
+        let (cmd, args) = line.split_once(' ').unwrap_or((line, ""));
+        match cmd {
+            "" => {}
+            "help" => writeln!(out, "{HELP}")?,
+            "quit" | "exit" => return Ok(false),
+            "show" => writeln!(out, "{}", self.doc)?,
+            "ids" => {
+                for (idx, (pos_id, atom)) in self.doc.iter_with_ids().enumerate() {
+                    writeln!(out, "{idx}\t{}\t{pos_id}", atom.label())?;
+                }
+            }
+            "tree" => write_tree(&self.doc.root, &mut PosID::new(), 0, out)?,
+            "dot" => match args.trim() {
+                "" => write!(out, "{}", self.doc.to_dot())?,
+                file => fs::write(file, self.doc.to_dot())?,
+            },
+            "insert" => {
+                let (pos, text) = args
+                    .split_once(' ')
+                    .ok_or_else(|| usage("insert <pos> <text>"))?;
+                let pos = parse_usize(pos, "insert <pos> <text>")?;
+                let text = unescape(text);
+                // One transaction, so a command that fails changes nothing
+                let sig = self
+                    .doc
+                    .transaction(|tx| tx.insert_str(CharOffset(pos), &text))?;
+                self.apply(sig)?;
+            }
+            "delete" => {
+                let mut args = args.split_whitespace();
+                let pos = parse_usize(args.next().unwrap_or(""), "delete <pos> [len]")?;
+                let len = match args.next() {
+                    Some(len) => parse_usize(len, "delete <pos> [len]")?,
+                    None => 1,
+                };
+                let sig = self
+                    .doc
+                    .transaction(|tx| tx.delete_range(pos..pos.saturating_add(len)))?;
+                self.apply(sig)?;
+            }
+            "save" => {
+                let file = file_arg(args, "save <file>")?;
+                fs::write(file, to_bytes(&self.log))?;
+            }
+            "load" => {
+                let file = file_arg(args, "load <file>")?;
+                let log: Vec<Signal> = from_bytes(&fs::read(file)?)?;
+                let mut doc = Treedoc::default();
+                for sig in log.iter().cloned() {
+                    doc.apply(sig)?;
+                }
+                self.doc = doc;
+                self.log = log;
+            }
+            "replay" => {
+                let file = file_arg(args, "replay <file>")?;
+                // A script replaying itself would never end
+                if self.replaying {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "replay can't be used in a replayed file",
+                    ));
+                }
+                let script = fs::read_to_string(file)?;
+                self.replaying = true;
+                let result = self.run_script(&script, out);
+                self.replaying = false;
+                result?;
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown command `{cmd}`, try `help`"),
+                ));
+            }
+        }
+        Ok(true)
+    }
+
+    // Errors of single commands are printed and the script goes on
+    fn run_script(&mut self, script: &str, out: &mut impl Write) -> Result<()> {
+        for line in script.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            writeln!(out, "> {}", line.trim_end())?;
+            match self.execute(line, out) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => writeln!(out, "error: {e}")?,
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, sig: Signal) -> Result<()> {
+        self.doc.apply(sig.clone())?;
+        self.log.push(sig);
+        Ok(())
+    }
+}
+
+fn usage(usage: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("usage: {usage}"))
+}
+
+fn parse_usize(arg: &str, usage_str: &str) -> Result<usize> {
+    arg.parse().map_err(|_| usage(usage_str))
+}
+
+fn file_arg<'a>(args: &'a str, usage_str: &str) -> Result<&'a str> {
+    match args.trim() {
+        "" => Err(usage(usage_str)),
+        file => Ok(file),
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("\\n", "\n").replace("\\t", "\t")
+}
+
+fn write_tree(
+    node: &Option<Rc<RefCell<Node>>>,
+    path: &mut PosID,
+    indent: usize,
+    out: &mut impl Write,
+) -> Result<()> {
+    let Some(node) = node else {
+        return Ok(());
+    };
+    let node = node.borrow();
+    writeln!(out, "{:indent$}major {path}", "")?;
+    path.0.push(PathComponent(0, None));
+    write_tree(&node.left, path, indent + 2, out)?;
+    path.0.pop();
+    for mini in node.children.borrow().iter() {
+        let mini = mini.borrow();
+        path.0.push(PathComponent(0, Some(mini.disambiguator)));
+        let tombstone = if mini.tombstone {
+            " (deleted)"
+        } else if mini.moved {
+            " (moved)"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "{:width$}mini {path} {}{tombstone}",
+            "",
+            mini.atom.label(),
+            width = indent + 2
+        )?;
+        path.0.push(PathComponent(0, None));
+        write_tree(&mini.left, path, indent + 4, out)?;
+        path.0.pop();
+        path.0.push(PathComponent(1, None));
+        write_tree(&mini.right, path, indent + 4, out)?;
+        path.0.pop();
+        path.0.pop();
+    }
+    path.0.push(PathComponent(1, None));
+    write_tree(&node.right, path, indent + 2, out)?;
+    path.0.pop();
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::io::{Error, ErrorKind, Result};
+
+use crate::{
+    node::{Atom, SDIS},
+    sequence::{SequenceCrdt, check_gap},
+};
+
+/*
+Replicated Growable Array (Roh et al.): every atom remembers the atom it was
+typed after. Atoms inserted after the same one are ordered newest first, by
+(lamport, site), and deleted atoms stay behind as tombstones so later inserts
+still find the atom they reference.
+*/
+
+/// (lamport, site) of the insert that created an atom
+pub type RgaId = (u64, SDIS);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RgaOp {
+    /// `after` is None for the start of the document
+    Insert {
+        id: RgaId,
+        after: Option<RgaId>,
+        atom: Atom,
+    },
+    Delete(RgaId),
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    id: RgaId,
+    atom: Atom,
+    deleted: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rga {
+    site: SDIS,
+    lamport: u64,
+    // Document order, tombstones included
+    elements: Vec<Element>,
+    len: usize,
+}
+
+impl Rga {
+    fn index_of(&self, id: RgaId) -> Option<usize> {
+        self.elements.iter().position(|e| e.id == id)
+    }
+
+    // Index into `elements` of the visible atom `index`
+    fn visible(&self, index: usize) -> Option<usize> {
+        self.elements
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| !e.deleted)
+            .nth(index)
+            .map(|(i, _)| i)
+    }
+}
+
+impl SequenceCrdt for Rga {
+    type Op = RgaOp;
+
+    fn with_site(site: SDIS) -> Self {
+        Rga {
+            site,
+            lamport: 0,
+            elements: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn site(&self) -> SDIS {
+        self.site
+    }
+
+    fn insert(&mut self, index: usize, atom: Atom) -> Result<RgaOp> {
+        check_gap(index, self.len)?;
+        let after = match index {
+            0 => None,
+            _ => self.visible(index - 1).map(|i| self.elements[i].id),
+        };
+        // Two inserts made before applying either still get their own ids
+        self.lamport += 1;
+        Ok(RgaOp::Insert {
+            id: (self.lamport, self.site),
+            after,
+            atom,
+        })
+    }
+
+    fn delete(&mut self, index: usize) -> Result<RgaOp> {
+        let i = self
+            .visible(index)
+            .ok_or(Error::from(ErrorKind::InvalidInput))?;
+        Ok(RgaOp::Delete(self.elements[i].id))
+    }
+
+    fn apply(&mut self, op: RgaOp) -> Result<()> {
+        match op {
+            RgaOp::Insert { id, after, atom } => {
+                if self.index_of(id).is_some() {
+                    return Ok(());
+                }
+                let mut i = match after {
+                    Some(after) => {
+                        self.index_of(after)
+                            .ok_or(Error::from(ErrorKind::NotFound))?
+                            + 1
+                    }
+                    None => 0,
+                };
+                // Newer inserts after the same atom, and everything typed after
+                // them, come first
+                while i < self.elements.len() && self.elements[i].id > id {
+                    i += 1;
+                }
+                self.elements.insert(
+                    i,
+                    Element {
+                        id,
+                        atom,
+                        deleted: false,
+                    },
+                );
+                self.lamport = self.lamport.max(id.0);
+                self.len += 1;
+            }
+            RgaOp::Delete(id) => {
+                let i = self.index_of(id).ok_or(Error::from(ErrorKind::NotFound))?;
+                if !self.elements[i].deleted {
+                    self.elements[i].deleted = true;
+                    self.len -= 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn atoms(&self) -> Vec<Atom> {
+        self.elements
+            .iter()
+            .filter(|e| !e.deleted)
+            .map(|e| e.atom.clone())
+            .collect()
+    }
+}
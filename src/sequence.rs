@@ -0,0 +1,99 @@
+use std::{
+    fmt::Debug,
+    io::{Error, ErrorKind, Result},
+};
+
+use crate::{
+    node::{Atom, SDIS},
+    offset::CharOffset,
+    treedoc::{Signal, Treedoc},
+};
+
+/*
+The part of the Treedoc API that any sequence CRDT can offer, so other
+algorithms (`rga::Rga`, `logoot::Logoot`) can run the same workloads and the
+same conformance tests. Edits work like on the Treedoc: `insert` and `delete`
+only describe the edit as an op, which then goes through `apply` locally and on
+every other replica.
+
+Ops have to be delivered in causal order, exactly once (the oplog takes care of
+that). Indices are 0-based chars.
+*/
+
+pub trait SequenceCrdt {
+    type Op: Clone + Debug;
+
+    /// An empty replica; `site` has to be unique among the replicas
+    fn with_site(site: SDIS) -> Self;
+
+    fn site(&self) -> SDIS;
+
+    /// An op inserting `atom` into the gap `index`
+    fn insert(&mut self, index: usize, atom: Atom) -> Result<Self::Op>;
+
+    /// An op deleting the atom at `index`
+    fn delete(&mut self, index: usize) -> Result<Self::Op>;
+
+    fn apply(&mut self, op: Self::Op) -> Result<()>;
+
+    /// Number of visible atoms
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The visible atoms in document order
+    fn atoms(&self) -> Vec<Atom>;
+
+    fn text(&self) -> String {
+        self.atoms().iter().collect()
+    }
+}
+
+impl SequenceCrdt for Treedoc {
+    type Op = Signal;
+
+    fn with_site(site: SDIS) -> Self {
+        Treedoc {
+            unique_disambiguator: site,
+            ..Treedoc::default()
+        }
+    }
+
+    fn site(&self) -> SDIS {
+        self.unique_disambiguator
+    }
+
+    fn insert(&mut self, index: usize, atom: Atom) -> Result<Signal> {
+        Ok(Signal::Insert(Treedoc::insert(
+            self,
+            CharOffset(index),
+            atom,
+        )?))
+    }
+
+    fn delete(&mut self, index: usize) -> Result<Signal> {
+        Ok(Signal::Delete(Treedoc::delete(self, CharOffset(index))?))
+    }
+
+    fn apply(&mut self, op: Signal) -> Result<()> {
+        Treedoc::apply(self, op)
+    }
+
+    fn len(&self) -> usize {
+        self.doc_length
+    }
+
+    fn atoms(&self) -> Vec<Atom> {
+        Treedoc::atoms(self).collect()
+    }
+}
+
+// Shared by the backends: the gap `index` has to exist
+pub(crate) fn check_gap(index: usize, len: usize) -> Result<()> {
+    if index > len {
+        return Err(Error::from(ErrorKind::InvalidInput));
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use crate::treedoc::Treedoc;
+
+/*
+Shape of the tree behind a document, for benchmarks and for spotting
+degenerate trees. PosID lengths count path components, so an atom in the root
+node has a PosID of length 1 and every step down adds one.
+*/
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TreeStats {
+    pub major_nodes: usize,
+    /// Atoms stored, tombstones and hidden move slots included
+    pub mininodes: usize,
+    pub tombstones: usize,
+    /// Length of the longest PosID in the tree
+    pub depth: usize,
+    /// Mean PosID length of the visible atoms
+    pub avg_pos_id_len: f64,
+}
+
+impl Treedoc {
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats::default();
+        let mut visible_len = 0;
+        let mut visible = 0;
+        // (major node, length of its path); iterative since typing at the end
+        // grows the tree as deep as the document is long
+        let mut stack: Vec<_> = self.root.iter().map(|root| (root.clone(), 0)).collect();
+        while let Some((node, len)) = stack.pop() {
+            let node = node.borrow();
+            stats.major_nodes += 1;
+            stack.extend(
+                node.left
+                    .iter()
+                    .chain(&node.right)
+                    .map(|n| (n.clone(), len + 1)),
+            );
+            for mini in node.children.borrow().iter() {
+                let mini = mini.borrow();
+                stats.mininodes += 1;
+                stats.tombstones += mini.tombstone as usize;
+                stats.depth = stats.depth.max(len + 1);
+                if mini.is_visible() {
+                    visible += 1;
+                    visible_len += len + 1;
+                }
+                stack.extend(
+                    mini.left
+                        .iter()
+                        .chain(&mini.right)
+                        .map(|n| (n.clone(), len + 2)),
+                );
+            }
+        }
+        if visible > 0 {
+            stats.avg_pos_id_len = visible_len as f64 / visible as f64;
+        }
+        stats
+    }
+}
@@ -0,0 +1,511 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Read, Result, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+#[cfg(feature = "websocket")]
+use crate::websocket;
+use crate::{
+    encoding::{Decode, Encode, from_bytes, read_u8, to_bytes},
+    node::SDIS,
+    oplog::{OpId, OpLog, VersionVector},
+    treedoc::{Signal, Treedoc},
+};
+
+/*
+Peers keep their whole op log and talk over TCP with u32 length-prefixed frames.
+Both ends of a new connection send a Hello with their version vector and answer
+the other's Hello with the ops it is missing; after that every op a peer applies
+is forwarded to all its other connections, so a host relays between the peers
+connected to it.
+
+The Treedoc lives on the thread owning the Peer. Socket threads only read frames
+and hand them over through a channel, `poll` applies them.
+*/
+
+/// Frames larger than this are treated as a protocol error
+pub const MAX_FRAME_LEN: usize = 64 << 20;
+
+/// Time a WebSocket peer gets to send its whole handshake
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Hello { site: SDIS, version: VersionVector },
+    Ops(Vec<(OpId, Signal)>),
+}
+
+/// A replica of a document syncing with other processes over TCP
+pub struct Peer {
+    pub doc: Treedoc,
+    pub log: OpLog,
+    site: SDIS,
+    net: Net,
+    // Received ops waiting for their causal predecessors, with the connection they came from
+    pending: Vec<(OpId, Signal, usize)>,
+}
+
+pub(crate) enum Event {
+    Connected(usize),
+    Frame(usize, Vec<u8>),
+    Closed(usize),
+}
+
+/// How messages are delimited on a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// u32 big-endian length prefix
+    Length,
+    /// WebSocket, accepted from a client
+    #[cfg(feature = "websocket")]
+    WsServer,
+    /// WebSocket, opened as a client
+    #[cfg(feature = "websocket")]
+    WsClient,
+}
+
+// What a connection's reader got from the socket
+pub(crate) enum Received {
+    Data(Vec<u8>),
+    #[cfg(feature = "websocket")]
+    Ping(Vec<u8>),
+    #[cfg(feature = "websocket")]
+    Close,
+}
+
+// What the socket threads send to the owning thread
+enum Incoming {
+    Accepted(usize, TcpStream, Framing),
+    Frame(usize, Vec<u8>),
+    #[cfg(feature = "websocket")]
+    Ping(usize, Vec<u8>),
+    Closed(usize),
+}
+
+// Open connections plus the threads feeding their frames into one channel.
+// Writes happen on the owning thread.
+pub(crate) struct Net {
+    conns: HashMap<usize, (TcpStream, Framing)>,
+    // Closed on this side, reported by the next poll
+    closed: Vec<usize>,
+    tx: Sender<Incoming>,
+    rx: Receiver<Incoming>,
+    next_conn: Arc<AtomicUsize>,
+    listeners: Vec<(SocketAddr, Arc<AtomicBool>)>,
+    // For WebSocket handshakes, taken by listeners when they start
+    pub(crate) handshake_timeout: Duration,
+}
+
+pub fn write_frame(stream: &mut impl Write, payload: &[u8]) -> Result<()> {
+    let len = u32::try_from(payload.len()).map_err(|_| Error::from(ErrorKind::InvalidInput))?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(payload)?;
+    stream.flush()
+}
+
+pub fn read_frame(stream: &mut impl Read) -> Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+impl Encode for Message {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Message::Hello { site, version } => {
+                buf.push(0);
+                site.encode(buf);
+                version.encode(buf);
+            }
+            Message::Ops(ops) => {
+                buf.push(1);
+                ops.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Message {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match read_u8(buf)? {
+            0 => Ok(Message::Hello {
+                site: u64::decode(buf)?,
+                version: VersionVector::decode(buf)?,
+            }),
+            1 => Ok(Message::Ops(Vec::decode(buf)?)),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+impl Framing {
+    // `partial` keeps a fragmented WebSocket message between calls
+    #[cfg_attr(not(feature = "websocket"), allow(unused_variables))]
+    fn read(self, stream: &mut TcpStream, partial: &mut Option<Vec<u8>>) -> Result<Received> {
+        match self {
+            Framing::Length => read_frame(stream).map(Received::Data),
+            #[cfg(feature = "websocket")]
+            Framing::WsServer => websocket::read_message(stream, true, partial),
+            #[cfg(feature = "websocket")]
+            Framing::WsClient => websocket::read_message(stream, false, partial),
+        }
+    }
+
+    fn write(self, stream: &mut TcpStream, payload: &[u8]) -> Result<()> {
+        match self {
+            Framing::Length => write_frame(stream, payload),
+            #[cfg(feature = "websocket")]
+            Framing::WsServer => websocket::write_message(stream, payload, false),
+            #[cfg(feature = "websocket")]
+            Framing::WsClient => websocket::write_message(stream, payload, true),
+        }
+    }
+
+    #[cfg(feature = "websocket")]
+    fn pong(self, stream: &mut TcpStream, payload: &[u8]) -> Result<()> {
+        match self {
+            Framing::Length => Ok(()),
+            Framing::WsServer => websocket::write_pong(stream, payload, false),
+            Framing::WsClient => websocket::write_pong(stream, payload, true),
+        }
+    }
+
+    // Best effort goodbye before the socket is shut down
+    #[cfg_attr(not(feature = "websocket"), allow(unused_variables))]
+    fn close(self, stream: &mut TcpStream) {
+        let _: Result<()> = match self {
+            Framing::Length => Ok(()),
+            #[cfg(feature = "websocket")]
+            Framing::WsServer => websocket::write_close(stream, false),
+            #[cfg(feature = "websocket")]
+            Framing::WsClient => websocket::write_close(stream, true),
+        };
+    }
+}
+
+impl Net {
+    pub(crate) fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            conns: HashMap::new(),
+            closed: Vec::new(),
+            tx,
+            rx,
+            next_conn: Arc::new(AtomicUsize::new(0)),
+            listeners: Vec::new(),
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+        }
+    }
+
+    // Accepts connections on `addr` in the background, returns the bound address
+    pub(crate) fn listen(
+        &mut self,
+        addr: impl ToSocketAddrs,
+        framing: Framing,
+    ) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let closed = Arc::new(AtomicBool::new(false));
+        let (tx, next_conn, closed_flag) =
+            (self.tx.clone(), self.next_conn.clone(), closed.clone());
+        let handshake_timeout = self.handshake_timeout;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if closed_flag.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let id = next_conn.fetch_add(1, Ordering::SeqCst);
+                spawn_reader(id, stream, framing, tx.clone(), Some(handshake_timeout));
+            }
+        });
+        self.listeners.push((local_addr, closed));
+        Ok(local_addr)
+    }
+
+    pub(crate) fn connect(&mut self, addr: impl ToSocketAddrs) -> Result<usize> {
+        let stream = TcpStream::connect(addr)?;
+        self.add(stream, Framing::Length)
+    }
+
+    // Opens a WebSocket to ws://addr/path
+    #[cfg(feature = "websocket")]
+    pub(crate) fn connect_ws(&mut self, addr: impl ToSocketAddrs, path: &str) -> Result<usize> {
+        let mut stream = TcpStream::connect(addr)?;
+        let host = stream.peer_addr()?.to_string();
+        websocket::client_handshake(&mut stream, &host, path, self.handshake_timeout)?;
+        self.add(stream, Framing::WsClient)
+    }
+
+    fn add(&mut self, stream: TcpStream, framing: Framing) -> Result<usize> {
+        let id = self.next_conn.fetch_add(1, Ordering::SeqCst);
+        stream.set_nodelay(true)?;
+        spawn_reader(id, stream.try_clone()?, framing, self.tx.clone(), None);
+        self.conns.insert(id, (stream, framing));
+        Ok(id)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.conns.len()
+    }
+
+    pub(crate) fn try_next(&mut self) -> Option<Event> {
+        if let Some(id) = self.closed.pop() {
+            return Some(Event::Closed(id));
+        }
+        loop {
+            let incoming = self.rx.try_recv().ok()?;
+            if let Some(event) = self.accept(incoming) {
+                return Some(event);
+            }
+        }
+    }
+
+    // Waits up to `timeout` for the next event
+    pub(crate) fn next_timeout(&mut self, timeout: Duration) -> Result<Option<Event>> {
+        if let Some(id) = self.closed.pop() {
+            return Ok(Some(Event::Closed(id)));
+        }
+        match self.rx.recv_timeout(timeout) {
+            Ok(incoming) => Ok(self.accept(incoming).or_else(|| self.try_next())),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::from(ErrorKind::BrokenPipe)),
+        }
+    }
+
+    // Frames of connections closed on this side may still be in the channel
+    fn accept(&mut self, incoming: Incoming) -> Option<Event> {
+        match incoming {
+            Incoming::Accepted(id, stream, framing) => {
+                stream.set_nodelay(true).ok()?;
+                self.conns.insert(id, (stream, framing));
+                Some(Event::Connected(id))
+            }
+            Incoming::Frame(id, payload) if self.conns.contains_key(&id) => {
+                Some(Event::Frame(id, payload))
+            }
+            #[cfg(feature = "websocket")]
+            Incoming::Ping(id, payload) => {
+                if let Some((stream, framing)) = self.conns.get_mut(&id)
+                    && framing.pong(stream, &payload).is_err()
+                {
+                    self.close(id);
+                }
+                None
+            }
+            Incoming::Closed(id) if self.conns.remove(&id).is_some() => Some(Event::Closed(id)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn send(&mut self, id: usize, msg: &impl Encode) {
+        let payload = to_bytes(msg);
+        let ok = match self.conns.get_mut(&id) {
+            Some((stream, framing)) => framing.write(stream, &payload).is_ok(),
+            None => true,
+        };
+        if !ok {
+            self.close(id);
+        }
+    }
+
+    pub(crate) fn broadcast(&mut self, msg: &impl Encode, except: Option<usize>) {
+        let ids: Vec<usize> = self.conns.keys().copied().collect();
+        for id in ids.into_iter().filter(|id| Some(*id) != except) {
+            self.send(id, msg);
+        }
+    }
+
+    pub(crate) fn close(&mut self, id: usize) {
+        if let Some((mut stream, framing)) = self.conns.remove(&id) {
+            framing.close(&mut stream);
+            let _ = stream.shutdown(Shutdown::Both);
+            self.closed.push(id);
+        }
+    }
+}
+
+impl Drop for Net {
+    fn drop(&mut self) {
+        let ids: Vec<usize> = self.conns.keys().copied().collect();
+        for id in ids {
+            self.close(id);
+        }
+        for (addr, closed) in &self.listeners {
+            closed.store(true, Ordering::SeqCst);
+            // Wake up the accept loop so it sees the flag
+            let _ = TcpStream::connect(addr);
+        }
+    }
+}
+
+// Accepted connections are handed to the owner from here, after the handshake.
+// `accepted` holds the time they get for it.
+fn spawn_reader(
+    id: usize,
+    mut stream: TcpStream,
+    framing: Framing,
+    tx: Sender<Incoming>,
+    accepted: Option<Duration>,
+) {
+    thread::spawn(move || {
+        if let Some(handshake_timeout) = accepted {
+            #[cfg(feature = "websocket")]
+            if framing == Framing::WsServer
+                && websocket::accept_handshake(&mut stream, handshake_timeout).is_err()
+            {
+                return;
+            }
+            #[cfg(not(feature = "websocket"))]
+            let _ = handshake_timeout;
+            let Ok(writer) = stream.try_clone() else {
+                return;
+            };
+            if tx.send(Incoming::Accepted(id, writer, framing)).is_err() {
+                return;
+            }
+        }
+        let mut partial = None;
+        while let Ok(received) = framing.read(&mut stream, &mut partial) {
+            let incoming = match received {
+                Received::Data(payload) => Incoming::Frame(id, payload),
+                #[cfg(feature = "websocket")]
+                Received::Ping(payload) => Incoming::Ping(id, payload),
+                #[cfg(feature = "websocket")]
+                Received::Close => break,
+            };
+            if tx.send(incoming).is_err() {
+                return;
+            }
+        }
+        let _ = tx.send(Incoming::Closed(id));
+    });
+}
+
+impl Peer {
+    /// An empty document edited as `site`, which must be unique among the peers
+    pub fn new(site: SDIS) -> Self {
+        let doc = Treedoc {
+            unique_disambiguator: site,
+            ..Treedoc::default()
+        };
+        Self {
+            doc,
+            log: OpLog::new(),
+            site,
+            net: Net::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn site(&self) -> SDIS {
+        self.site
+    }
+
+    /// Accepts peers on `addr` in the background, returns the bound address
+    pub fn listen(&mut self, addr: impl ToSocketAddrs) -> Result<SocketAddr> {
+        self.net.listen(addr, Framing::Length)
+    }
+
+    pub fn connect(&mut self, addr: impl ToSocketAddrs) -> Result<()> {
+        let id = self.net.connect(addr)?;
+        self.hello(id);
+        Ok(())
+    }
+
+    pub fn connections(&self) -> usize {
+        self.net.len()
+    }
+
+    /// Applies a signal made on this replica and sends it to every peer
+    pub fn apply_local(&mut self, sig: Signal) -> Result<OpId> {
+        self.doc.apply(sig.clone())?;
+        let id = self.log.push_local(self.site, sig.clone());
+        self.net.broadcast(&Message::Ops(vec![(id, sig)]), None);
+        Ok(id)
+    }
+
+    /// Handles everything received so far without blocking
+    pub fn poll(&mut self) -> Result<()> {
+        while let Some(event) = self.net.try_next() {
+            self.handle(event)?;
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for network activity and handles it.
+    /// Returns false if nothing arrived.
+    pub fn poll_timeout(&mut self, timeout: Duration) -> Result<bool> {
+        match self.net.next_timeout(timeout)? {
+            Some(event) => {
+                self.handle(event)?;
+                self.poll()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn hello(&mut self, id: usize) {
+        let hello = Message::Hello {
+            site: self.site,
+            version: self.log.version().clone(),
+        };
+        self.net.send(id, &hello);
+    }
+
+    fn handle(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::Connected(id) => self.hello(id),
+            Event::Closed(_) => {}
+            Event::Frame(id, payload) => match from_bytes(&payload) {
+                Ok(Message::Hello { site, version }) => {
+                    if site == self.site {
+                        // Two replicas with one site id would hand out clashing PosIDs
+                        self.net.close(id);
+                        return Ok(());
+                    }
+                    let missing = self.log.missing_for(&version);
+                    if !missing.is_empty() {
+                        self.net.send(id, &Message::Ops(missing));
+                    }
+                }
+                Ok(Message::Ops(ops)) => {
+                    for (op_id, sig) in ops {
+                        if !self.log.contains(&op_id)
+                            && self.pending.iter().all(|(p, _, _)| *p != op_id)
+                        {
+                            self.pending.push((op_id, sig, id));
+                        }
+                    }
+                    let net = &mut self.net;
+                    // Forwarded so peers only connected through this one get it too
+                    let forward = |op_id, sig: &Signal, from: &usize| {
+                        net.broadcast(&Message::Ops(vec![(op_id, sig.clone())]), Some(*from));
+                    };
+                    let rejected = self.log.deliver(&mut self.doc, &mut self.pending, forward);
+                    // An op that can't be applied is a protocol error of the peer that sent it
+                    for from in rejected {
+                        self.net.close(from);
+                    }
+                }
+                Err(_) => self.net.close(id),
+            },
+        }
+        Ok(())
+    }
+}
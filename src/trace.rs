@@ -0,0 +1,331 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::Path,
+};
+
+use crate::{node::Atom, sequence::SequenceCrdt};
+
+/*
+Recorded editing sessions, replayed to benchmark and regression test the
+backends. A trace is a list of `(pos, delete_count, inserted_text)` patches
+counted in chars, in one of the JSON layouts in circulation:
+
+- automerge-perf: `{"edits": [[pos, del, text?], ...], "finalText": "..."}`
+- editing-traces: `{"startContent": "", "endContent": "...",
+  "txns": [{"patches": [[pos, del, text], ...]}, ...]}`
+- or just the array of patches
+
+Files have to be decompressed first. Only as much JSON as these layouts need is
+understood.
+*/
+
+/// Delete `del` chars at `pos`, then insert `text` there
+#[derive(Debug, Clone, PartialEq)]
+pub struct Patch {
+    pub pos: usize,
+    pub del: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    /// The document before the first patch
+    pub start: String,
+    pub patches: Vec<Patch>,
+    /// The text the patches have to end in, if the trace records it
+    pub end: Option<String>,
+}
+
+impl Trace {
+    pub fn parse(json: &str) -> Result<Self> {
+        let mut parser = Parser {
+            src: json.as_bytes(),
+            at: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_ws();
+        if parser.at != parser.src.len() {
+            return Err(invalid());
+        }
+        Self::from_json(value)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn from_json(value: Json) -> Result<Self> {
+        let mut obj = match value {
+            Json::Array(patches) => {
+                return Ok(Trace {
+                    patches: patches.into_iter().map(patch).collect::<Result<_>>()?,
+                    ..Trace::default()
+                });
+            }
+            Json::Object(obj) => obj,
+            _ => return Err(invalid()),
+        };
+        let mut text = |key| match obj.remove(key) {
+            Some(Json::String(s)) => Ok(Some(s)),
+            None => Ok(None),
+            Some(_) => Err(invalid()),
+        };
+        let start = text("startContent")?.unwrap_or_default();
+        let end = match text("endContent")? {
+            Some(end) => Some(end),
+            None => text("finalText")?,
+        };
+        let patches = match (obj.remove("edits"), obj.remove("txns")) {
+            (Some(Json::Array(edits)), None) => edits,
+            (None, Some(Json::Array(txns))) => {
+                let mut patches = Vec::new();
+                for txn in txns {
+                    match txn {
+                        Json::Object(mut txn) => match txn.remove("patches") {
+                            Some(Json::Array(p)) => patches.extend(p),
+                            _ => return Err(invalid()),
+                        },
+                        _ => return Err(invalid()),
+                    }
+                }
+                patches
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Trace {
+            start,
+            patches: patches.into_iter().map(patch).collect::<Result<_>>()?,
+            end,
+        })
+    }
+
+    /// Types the start content and every patch into `doc`, applying the ops as
+    /// it goes. Checks the result against the recorded end text (InvalidData)
+    /// and returns the ops, for replaying on other replicas.
+    pub fn replay<S: SequenceCrdt>(&self, doc: &mut S) -> Result<Vec<S::Op>> {
+        let mut ops = Vec::new();
+        let start = Patch {
+            pos: 0,
+            del: 0,
+            text: self.start.clone(),
+        };
+        for patch in std::iter::once(&start).chain(&self.patches) {
+            for _ in 0..patch.del {
+                let op = doc.delete(patch.pos)?;
+                doc.apply(op.clone())?;
+                ops.push(op);
+            }
+            for (i, ch) in patch.text.chars().enumerate() {
+                let op = doc.insert(patch.pos + i, Atom::Char(ch))?;
+                doc.apply(op.clone())?;
+                ops.push(op);
+            }
+        }
+        if self.end.as_ref().is_some_and(|end| *end != doc.text()) {
+            return Err(invalid());
+        }
+        Ok(ops)
+    }
+}
+
+fn invalid() -> Error {
+    Error::from(ErrorKind::InvalidData)
+}
+
+// [pos, del] or [pos, del, text]
+fn patch(value: Json) -> Result<Patch> {
+    let Json::Array(fields) = value else {
+        return Err(invalid());
+    };
+    let count = |field: Option<&Json>| match field {
+        Some(Json::Number(n)) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+        _ => Err(invalid()),
+    };
+    let text = match fields.get(2) {
+        Some(Json::String(s)) => s.clone(),
+        None => String::new(),
+        Some(_) => return Err(invalid()),
+    };
+    if fields.len() > 3 {
+        return Err(invalid());
+    }
+    Ok(Patch {
+        pos: count(fields.first())?,
+        del: count(fields.get(1))?,
+        text,
+    })
+}
+
+enum Json {
+    Null,
+    Bool,
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn skip_ws(&mut self) {
+        while self.src.get(self.at).is_some_and(u8::is_ascii_whitespace) {
+            self.at += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Result<u8> {
+        self.skip_ws();
+        self.src.get(self.at).copied().ok_or(invalid())
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.peek()? != byte {
+            return Err(invalid());
+        }
+        self.at += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json> {
+        if !self.src[self.at..].starts_with(word.as_bytes()) {
+            return Err(invalid());
+        }
+        self.at += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        match self.peek()? {
+            b'n' => self.literal("null", Json::Null),
+            b't' => self.literal("true", Json::Bool),
+            b'f' => self.literal("false", Json::Bool),
+            b'"' => Ok(Json::String(self.string()?)),
+            b'[' => {
+                self.at += 1;
+                let mut items = Vec::new();
+                if self.peek()? == b']' {
+                    self.at += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    match self.peek()? {
+                        b',' => self.at += 1,
+                        b']' => break,
+                        _ => return Err(invalid()),
+                    }
+                }
+                self.at += 1;
+                Ok(Json::Array(items))
+            }
+            b'{' => {
+                self.at += 1;
+                let mut fields = BTreeMap::new();
+                if self.peek()? == b'}' {
+                    self.at += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.peek()?;
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.insert(key, self.value()?);
+                    match self.peek()? {
+                        b',' => self.at += 1,
+                        b'}' => break,
+                        _ => return Err(invalid()),
+                    }
+                }
+                self.at += 1;
+                Ok(Json::Object(fields))
+            }
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.at;
+        while self
+            .src
+            .get(self.at)
+            .is_some_and(|b| b.is_ascii_digit() || b"+-.eE".contains(b))
+        {
+            self.at += 1;
+        }
+        std::str::from_utf8(&self.src[start..self.at])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Json::Number)
+            .ok_or(invalid())
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        loop {
+            let start = self.at;
+            while self
+                .src
+                .get(self.at)
+                .is_some_and(|b| *b != b'"' && *b != b'\\')
+            {
+                self.at += 1;
+            }
+            // Slicing at ASCII bytes keeps the input's UTF-8 intact
+            out.push_str(std::str::from_utf8(&self.src[start..self.at]).map_err(|_| invalid())?);
+            match self.src.get(self.at) {
+                Some(b'"') => {
+                    self.at += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    let escape = *self.src.get(self.at + 1).ok_or(invalid())?;
+                    self.at += 2;
+                    out.push(match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(invalid()),
+                    });
+                }
+                _ => return Err(invalid()),
+            }
+        }
+    }
+
+    // After `\u`; chars outside the BMP come as a surrogate pair
+    fn unicode_escape(&mut self) -> Result<char> {
+        let high = self.hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or(invalid());
+        }
+        if !self.src[self.at..].starts_with(b"\\u") {
+            return Err(invalid());
+        }
+        self.at += 2;
+        let low = self.hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(invalid());
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)).ok_or(invalid())
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let digits = self.src.get(self.at..self.at + 4).ok_or(invalid())?;
+        let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+        self.at += 4;
+        u32::from_str_radix(digits, 16).map_err(|_| invalid())
+    }
+}
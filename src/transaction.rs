@@ -0,0 +1,151 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    ops::Range,
+};
+
+use crate::{
+    events::Change,
+    marks::{Mark, MarkType},
+    node::Atom,
+    offset::{CharOffset, TextOffset},
+    treedoc::{Signal, Treedoc, Undo},
+};
+
+/*
+A transaction is a single signal holding several edits. Replicas apply it
+all-or-nothing: if one of the edits fails (say its parent hasn't arrived yet)
+the ones before it are rolled back, so nobody ever sees half of it. Subscribers
+get one `Change::Transaction` with everything it did.
+*/
+
+/// Edits being collected into a `Signal::Transaction`. Each one sees the ones
+/// before it, so positions refer to the text as edited so far.
+pub struct Transaction<'a> {
+    doc: &'a mut Treedoc,
+    signals: Vec<Signal>,
+    undo: Vec<Undo>,
+}
+
+impl Transaction<'_> {
+    /// The document with the edits so far
+    pub fn doc(&self) -> &Treedoc {
+        self.doc
+    }
+
+    pub fn insert(&mut self, pos: impl TextOffset, atom: impl Into<Atom>) -> Result<()> {
+        let sig = self.doc.insert(pos, atom)?;
+        self.push(Signal::Insert(sig))
+    }
+
+    /// Inserts every char of `text` in order starting at `pos`
+    pub fn insert_str(&mut self, pos: impl TextOffset, text: &str) -> Result<()> {
+        let start = self.doc.to_char_offset(pos)?.0;
+        for (i, ch) in text.chars().enumerate() {
+            self.insert(CharOffset(start + i), ch)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete(&mut self, pos: impl TextOffset) -> Result<()> {
+        let sig = self.doc.delete(pos)?;
+        self.push(Signal::Delete(sig))
+    }
+
+    /// Deletes the chars in `range`
+    pub fn delete_range(&mut self, range: Range<usize>) -> Result<()> {
+        for _ in range.clone() {
+            self.delete(CharOffset(range.start))?;
+        }
+        Ok(())
+    }
+
+    pub fn mark(&mut self, range: Range<usize>, mark: Mark) -> Result<()> {
+        let sig = self.doc.mark(range, mark)?;
+        self.push(Signal::Mark(sig))
+    }
+
+    pub fn unmark(&mut self, range: Range<usize>, mark_type: MarkType) -> Result<()> {
+        let sig = self.doc.unmark(range, mark_type)?;
+        self.push(Signal::Mark(sig))
+    }
+
+    /// Moves the atom at `from` to the gap `to`, see `Treedoc::move_atom`
+    pub fn move_atom(&mut self, from: usize, to: usize) -> Result<()> {
+        let sig = self.doc.move_atom(from, to)?;
+        self.push(Signal::Move(sig))
+    }
+
+    /// Moves the chars in `range` to the gap `to`, which may not be inside it
+    pub fn move_range(&mut self, range: Range<usize>, to: usize) -> Result<()> {
+        if range.end > self.doc.doc_length || (range.start < to && to < range.end) {
+            return Err(Error::from(ErrorKind::InvalidInput));
+        }
+        for i in 0..range.len() {
+            // Each atom goes right after the ones moved before it
+            if to <= range.start {
+                self.move_atom(range.start + i, to + i)?;
+            } else {
+                self.move_atom(range.start, to)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, sig: Signal) -> Result<()> {
+        self.doc.apply_recorded(sig.clone(), &mut self.undo)?;
+        self.signals.push(sig);
+        Ok(())
+    }
+}
+
+impl Treedoc {
+    /// Runs `edits` and returns them as one signal. Like the single edit methods
+    /// this leaves the document as it was; apply the signal to make the change.
+    pub fn transaction(
+        &mut self,
+        edits: impl FnOnce(&mut Transaction) -> Result<()>,
+    ) -> Result<Signal> {
+        // Nothing is reported for the trial run
+        let outer = self.subscribers.begin_batch();
+        let mut tx = Transaction {
+            doc: self,
+            signals: Vec::new(),
+            undo: Vec::new(),
+        };
+        let result = edits(&mut tx);
+        let Transaction { signals, undo, .. } = tx;
+        self.revert(undo);
+        self.subscribers.end_batch(outer);
+        result?;
+        Ok(Signal::Transaction(signals))
+    }
+
+    pub(crate) fn apply_transaction(
+        &mut self,
+        sigs: Vec<Signal>,
+        undo: &mut Vec<Undo>,
+    ) -> Result<()> {
+        if sigs.iter().any(|sig| matches!(sig, Signal::Transaction(_))) {
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+        let outer = self.subscribers.begin_batch();
+        let mut applied = Vec::new();
+        let result = sigs
+            .into_iter()
+            .try_for_each(|sig| self.apply_recorded(sig, &mut applied));
+        let changes = self.subscribers.end_batch(outer);
+        match result {
+            Ok(()) => {
+                undo.extend(applied);
+                if !changes.is_empty() {
+                    self.emit(Change::Transaction(changes));
+                }
+                Ok(())
+            }
+            Err(e) => {
+                self.revert(applied);
+                Err(e)
+            }
+        }
+    }
+}
@@ -1,40 +1,74 @@
 use std::{
     cell::RefCell,
-    collections::VecDeque,
+    collections::{BTreeMap, HashMap},
+    fmt,
     io::{Error, Result},
+    ops::{Bound, RangeBounds},
     rc::Rc,
-    usize,
 };
 
 use crate::{
-    node::{AtPosition, Atom, Mininode, Node, SDIS},
-    pos_id::{PathComponent, PosID},
+    allocation::Allocation,
+    events::{Change, Subscribers},
+    history::ClockVector,
+    iter::{Atoms, AtomsWithIds, TreedocIter},
+    marks::MarkSignal,
+    moves::{MoveIndex, MoveSignal},
+    node::{AtPosition, Atom, Mininode, Node, SDIS, subtree_hash, subtree_len},
+    offset::{CharOffset, TextLength, TextOffset, Unit, Utf8Offset, Utf16Offset},
+    pos_id::{CompactPosID, PathComponent, PosID},
 };
 
-struct TreedocIter {
-    paths: VecDeque<PosID>,
-}
+// An atom's major node and its own mininode
+type AtomNodes = (Rc<RefCell<Node>>, Rc<RefCell<Mininode>>);
+
+// A subtree one step below a position, with its hash
+type Child = (PathComponent, AtPosition, u64);
 
 // Could also be implemented as a buffer on Treedoc??
 // -> depends on the sync strat later
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InsertSignal {
-    atom: Atom,
-    pos_id: PosID,
-    unique_disambiguator: SDIS,
+    pub(crate) atom: Atom,
+    // Bit-packed as on the wire, since op logs keep every signal
+    pub(crate) pos_id: CompactPosID,
+    pub(crate) unique_disambiguator: SDIS,
+    // Lamport clock of the insert; the disambiguator is the site making it
+    pub(crate) lamport: u64,
+    // Missing major nodes right above the new one that are created empty,
+    // see `allocation`
+    pub(crate) grow: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DeleteSignal {
-    pos_id: PosID,
-    // For concurrency
-    _unique_disambiguator: SDIS,
+    pub(crate) pos_id: CompactPosID,
+    // Site making the delete
+    pub(crate) unique_disambiguator: SDIS,
+    pub(crate) lamport: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Signal {
     Insert(InsertSignal),
     Delete(DeleteSignal),
+    Mark(MarkSignal),
+    Move(MoveSignal),
+    /// Edits applied all-or-nothing, see `Treedoc::transaction`. Does not nest.
+    Transaction(Vec<Signal>),
+}
+
+// How to take back one applied signal
+#[derive(Debug)]
+pub(crate) enum Undo {
+    // A mininode added to the existing major node at the path
+    Mini(PosID, SDIS),
+    // A new major node at the path
+    Node(PosID),
+    // A delete of the atom at the path, by its stamp
+    Tombstone(PosID, (u64, SDIS)),
+    Mark((u64, SDIS)),
+    Move((u64, SDIS)),
 }
 
 #[derive(Debug)]
@@ -42,117 +76,349 @@ pub struct Treedoc {
     pub root: Option<Rc<RefCell<Node>>>,
     pub unique_disambiguator: SDIS,
     pub doc_length: usize,
+    /// Lamport clock ordering formatting operations
+    pub lamport: u64,
+    pub marks: Vec<MarkSignal>,
+    /// Every move applied, in the order they arrived
+    pub moves: Vec<MoveSignal>,
+    /// `moves` by moved atom and by slot, kept in step with it
+    pub move_index: MoveIndex,
+    /// Callbacks told about every change to the visible text
+    pub subscribers: Subscribers,
+    /// Where atoms typed at the end go
+    pub allocation: Allocation,
+    /// Lamport clock of the latest edit of every site applied, see `history`
+    pub version: ClockVector,
+    /// Named versions, see `checkpoint`
+    pub checkpoints: BTreeMap<String, ClockVector>,
+}
+
+impl InsertSignal {
+    // Path of the inserted atom's mininode. A slot step makes a node holding it
+    // (or joins one) under the inserting site's disambiguator.
+    pub(crate) fn atom_path(&self) -> PosID {
+        let mut path = self.pos_id.to_pos_id();
+        if path.0.last().is_some_and(|c| c.1.is_none()) {
+            path.0
+                .push(PathComponent(0, Some(self.unique_disambiguator)));
+        }
+        path
+    }
+}
+
+impl fmt::Display for Treedoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for atom in self.atoms() {
+            write!(f, "{atom}")?;
+        }
+        Ok(())
+    }
 }
 
-impl Iterator for TreedocIter {
-    type Item = PosID;
-    fn next(&mut self) -> Option<Self::Item> {
-        self.paths.pop_front()
+impl Default for Treedoc {
+    /// An empty document
+    fn default() -> Self {
+        Treedoc {
+            root: Some(Rc::new(RefCell::new(Node::new()))),
+            doc_length: 0,
+            unique_disambiguator: 1u64,
+            lamport: 0,
+            marks: Vec::new(),
+            moves: Vec::new(),
+            move_index: MoveIndex::default(),
+            subscribers: Subscribers::default(),
+            allocation: Allocation::default(),
+            version: ClockVector::new(),
+            checkpoints: BTreeMap::new(),
+        }
     }
 }
 
 impl Treedoc {
-    pub fn new(ch: char) -> Self {
-        let root = Node::new_with_mini(ch, 1u64);
+    pub fn new(atom: impl Into<Atom>) -> Self {
+        let root = Node::new_with_mini(atom.into(), 1u64);
         Treedoc {
             root: Some(Rc::new(RefCell::new(root))),
             doc_length: 1,
             unique_disambiguator: 1u64,
+            lamport: 0,
+            marks: Vec::new(),
+            moves: Vec::new(),
+            move_index: MoveIndex::default(),
+            subscribers: Subscribers::default(),
+            allocation: Allocation::default(),
+            version: ClockVector::new(),
+            checkpoints: BTreeMap::new(),
         }
     }
     pub fn apply(&mut self, sig: Signal) -> Result<()> {
+        let mut stamps = Vec::new();
+        sig.stamps(&mut stamps);
+        self.apply_recorded(sig, &mut Vec::new())?;
+        for stamp in stamps {
+            self.version.observe(stamp);
+        }
+        Ok(())
+    }
+
+    // Applies `sig` and notes in `undo` how to take it back. Nothing is changed
+    // when it fails.
+    pub(crate) fn apply_recorded(&mut self, sig: Signal, undo: &mut Vec<Undo>) -> Result<()> {
+        let (lamport, done) = (self.lamport, undo.len());
+        let result = self.apply_signal(sig, undo);
+        if result.is_err() {
+            // Parts of the signal may have gone in before it failed
+            let partial = undo.split_off(done);
+            self.revert(partial);
+            self.lamport = lamport;
+        }
+        result
+    }
+
+    fn apply_signal(&mut self, sig: Signal, undo: &mut Vec<Undo>) -> Result<()> {
         match sig {
             Signal::Insert(op) => {
-                if let Some((last, rest)) = op.pos_id.0.split_last() {
-                    let vd: Vec<PathComponent> = rest.iter().cloned().collect();
-                    match Self::traverse_node_at_pos_id(AtPosition::Major(self.root.clone()), &vd) {
-                        AtPosition::Major(parent_node) => match (last.0, last.1) {
-                            (_, Some(dis)) => {
-                                // push a child node, do not assign
-                                parent_node
-                                    .unwrap()
-                                    .borrow_mut()
-                                    .add_mini(Mininode::new_with_atom(op.atom, dis));
-                            }
-                            (0, None) => {
-                                let new_node =
-                                    Node::new_with_mini(op.atom, op.unique_disambiguator);
-                                parent_node.unwrap().borrow_mut().add_left(new_node);
-                            }
-                            (1, None) => {
-                                let new_node =
-                                    Node::new_with_mini(op.atom, op.unique_disambiguator);
-                                parent_node.unwrap().borrow_mut().add_right(new_node);
-                            }
-                            _ => {
-                                unreachable!()
-                            }
-                        },
-                        AtPosition::Mini(parent_node) => match (last.0, last.1) {
-                            (0, None) => {
-                                let new_node =
-                                    Node::new_with_mini(op.atom, op.unique_disambiguator);
-                                parent_node.unwrap().borrow_mut().add_left(new_node);
-                            }
-                            (1, None) => {
-                                let new_node =
-                                    Node::new_with_mini(op.atom, op.unique_disambiguator);
-                                parent_node.unwrap().borrow_mut().add_right(new_node);
-                            }
-                            _ => {
-                                unreachable!()
-                            }
-                        },
-                    }
-                    self.doc_length += 1;
-                    return Ok(());
+                let ch = op.atom.to_char();
+                self.lamport = self.lamport.max(op.lamport);
+                let atom_path = self.insert_mini(op, undo)?;
+                if !self.moves.is_empty() {
+                    // Snapshots can bring an atom after the moves of it
+                    self.settle_moves(&atom_path);
+                }
+                self.doc_length = subtree_len(&self.root).chars;
+                if !self.subscribers.is_empty()
+                    && let Some(index) = self.visible_index(&atom_path)
+                {
+                    let text = ch.to_string();
+                    self.emit(Change::Inserted { index, text });
                 }
-                Err(Error::from(std::io::ErrorKind::InvalidData))
+                Ok(())
             }
             Signal::Delete(op) => {
-                if let AtPosition::Mini(Some(node)) = Self::traverse_node_at_pos_id(
-                    AtPosition::Major(self.root.clone()),
-                    &op.pos_id.0,
-                ) {
-                    let mut node_mut = node.borrow_mut();
-                    if node_mut.left.is_some() || node_mut.right.is_some() {
-                        node_mut.tombstone = true;
-                        self.doc_length -= 1;
-                        return Ok(());
+                // Deleted atoms always stay behind as tombstones: a concurrent insert may
+                // still reference them, and deleting twice only records who did. A moved
+                // atom is deleted by its identity, wherever it is shown.
+                let origin = self.origin_of(&op.pos_id.to_pos_id());
+                self.own_path(&origin.0);
+                let Some(mini) = self.mini_at(&origin) else {
+                    return Err(Error::from(std::io::ErrorKind::NotFound));
+                };
+                self.lamport = self.lamport.max(op.lamport);
+                let stamp = (op.lamport, op.unique_disambiguator);
+                let Err(at) = mini.borrow().deleted.binary_search(&stamp) else {
+                    // Delivered before
+                    return Ok(());
+                };
+                mini.borrow_mut().deleted.insert(at, stamp);
+                if mini.borrow().tombstone {
+                    // Only the stamps changed, which the hash covers
+                    self.update_on_path(&origin.0);
+                    undo.push(Undo::Tombstone(origin, stamp));
+                } else {
+                    let index = if self.subscribers.is_empty() {
+                        None
+                    } else {
+                        self.pos_of(&origin)
+                    };
+                    mini.borrow_mut().tombstone = true;
+                    self.update_on_path(&origin.0);
+                    if !self.moves.is_empty() {
+                        self.settle_moves(&origin);
                     }
+                    self.doc_length = subtree_len(&self.root).chars;
+                    undo.push(Undo::Tombstone(origin, stamp));
+                    if let Some(index) = index {
+                        self.emit(Change::Deleted { index, len: 1 });
+                    }
+                }
+                Ok(())
+            }
+            Signal::Mark(op) => {
+                let id = op.id;
+                if self.apply_mark(op) {
+                    undo.push(Undo::Mark(id));
+                }
+                Ok(())
+            }
+            Signal::Move(op) => self.apply_move(op, undo),
+            Signal::Transaction(sigs) => self.apply_transaction(sigs, undo),
+        }
+    }
+
+    // Puts the atom of an insert into the tree and returns the path of its
+    // mininode. Leaves `doc_length` to the caller.
+    pub(crate) fn insert_mini(&mut self, op: InsertSignal, undo: &mut Vec<Undo>) -> Result<PosID> {
+        if self.root.is_none() {
+            self.root = Some(Rc::new(RefCell::new(Node::new())));
+        }
+        let pos_id = op.pos_id.to_pos_id();
+        // Copied first if a fork shares it, see `branch`
+        self.own_path(&pos_id.0);
+        let atom_path = op.atom_path();
+        let Some((last, rest)) = pos_id.0.split_last() else {
+            return Err(Error::from(std::io::ErrorKind::InvalidData));
+        };
+        let vd: Vec<PathComponent> = rest.to_vec();
+        if !matches!(last, PathComponent(0, Some(_)) | PathComponent(0 | 1, None)) {
+            return Err(Error::from(std::io::ErrorKind::InvalidData));
+        }
+        if let Some(grown) = self.grow_path(&vd, op.grow)? {
+            undo.push(Undo::Node(grown));
+        }
+        let mini = |dis| {
+            let mut mini = Mininode {
+                inserted: (op.lamport, dis),
+                ..Mininode::new_with_atom(op.atom.clone(), dis)
+            };
+            mini.update_hash();
+            mini
+        };
+        let parent = Self::traverse_node_at_pos_id(AtPosition::Major(self.root.clone()), &vd);
+        match (&parent, last) {
+            (AtPosition::Major(Some(node)), PathComponent(0, Some(dis))) => {
+                // push a child node, do not assign
+                Self::add_unique_mini(node, mini(*dis))?;
+                undo.push(Undo::Mini(PosID(vd.clone()), *dis));
+            }
+            (
+                AtPosition::Major(Some(_)) | AtPosition::Mini(Some(_)),
+                PathComponent(dir @ (0 | 1), None),
+            ) => match Self::step(&parent, last) {
+                // Concurrent inserts into the same slot become minisiblings
+                AtPosition::Major(Some(existing)) => {
+                    Self::add_unique_mini(&existing, mini(op.unique_disambiguator))?;
+                    undo.push(Undo::Mini(pos_id.clone(), op.unique_disambiguator));
+                }
+                _ => {
+                    let new_node = Node::from_mini(mini(op.unique_disambiguator));
+                    Self::add_child(&parent, *dir, new_node);
+                    undo.push(Undo::Node(pos_id.clone()));
                 }
-                if let Some((last, rest)) = op.pos_id.0.split_last() {
-                    let vd: Vec<PathComponent> = rest.iter().cloned().collect();
+            },
+            // Parent not delivered yet
+            (AtPosition::Major(None) | AtPosition::Mini(None), _) => {
+                return Err(Error::from(std::io::ErrorKind::NotFound));
+            }
+            _ => return Err(Error::from(std::io::ErrorKind::InvalidData)),
+        }
+        self.update_on_path(&pos_id.0);
+        Ok(atom_path)
+    }
+
+    // Creates the last `grow` major nodes of `path` that are missing, empty.
+    // What comes before them has to exist. Returns the path of the topmost
+    // node created.
+    fn grow_path(&self, path: &[PathComponent], grow: usize) -> Result<Option<PosID>> {
+        if grow == 0 {
+            return Ok(None);
+        }
+        let Some(start) = path.len().checked_sub(grow) else {
+            return Err(Error::from(std::io::ErrorKind::InvalidData));
+        };
+        if !path[start..].iter().all(|c| c.0 <= 1 && c.1.is_none()) {
+            return Err(Error::from(std::io::ErrorKind::InvalidData));
+        }
+        let mut at = Self::traverse_node_at_pos_id(
+            AtPosition::Major(self.root.clone()),
+            &path[..start].to_vec(),
+        );
+        if matches!(at, AtPosition::Major(None) | AtPosition::Mini(None)) {
+            return Err(Error::from(std::io::ErrorKind::NotFound));
+        }
+        let mut grown = None;
+        for (i, comp) in path.iter().enumerate().skip(start) {
+            let mut next = Self::step(&at, comp);
+            if let AtPosition::Major(None) = next {
+                Self::add_child(&at, comp.0, Node::new());
+                grown.get_or_insert_with(|| PosID(path[..=i].to_vec()));
+                next = Self::step(&at, comp);
+            }
+            at = next;
+        }
+        Ok(grown)
+    }
+
+    fn add_child(parent: &AtPosition, dir: usize, node: Node) {
+        match (parent, dir) {
+            (AtPosition::Major(Some(parent)), 0) => parent.borrow_mut().add_left(node),
+            (AtPosition::Major(Some(parent)), _) => parent.borrow_mut().add_right(node),
+            (AtPosition::Mini(Some(parent)), 0) => parent.borrow_mut().add_left(node),
+            (AtPosition::Mini(Some(parent)), _) => parent.borrow_mut().add_right(node),
+            _ => unreachable!(),
+        }
+    }
+
+    // Takes back applied signals, newest first
+    pub(crate) fn revert(&mut self, undo: Vec<Undo>) {
+        for step in undo.into_iter().rev() {
+            match step {
+                Undo::Mini(path, dis) => {
+                    self.own_path(&path.0);
                     if let AtPosition::Major(Some(node)) =
-                        Self::traverse_node_at_pos_id(AtPosition::Major(self.root.clone()), &vd)
+                        Self::traverse_node_at_pos_id(AtPosition::Major(self.root.clone()), &path.0)
+                    {
+                        let _ = node.borrow().remove_mini(Some(dis));
+                        self.update_on_path(&path.0);
+                    }
+                }
+                Undo::Node(path) => {
+                    let Some((last, parent_path)) = path.0.split_last() else {
+                        continue;
+                    };
+                    let parent_path = parent_path.to_vec();
+                    self.own_path(&parent_path);
+                    match Self::traverse_node_at_pos_id(
+                        AtPosition::Major(self.root.clone()),
+                        &parent_path,
+                    ) {
+                        AtPosition::Major(Some(node)) if last.0 == 0 => {
+                            node.borrow_mut().left = None
+                        }
+                        AtPosition::Major(Some(node)) => node.borrow_mut().right = None,
+                        AtPosition::Mini(Some(node)) if last.0 == 0 => {
+                            node.borrow_mut().left = None
+                        }
+                        AtPosition::Mini(Some(node)) => node.borrow_mut().right = None,
+                        _ => continue,
+                    }
+                    self.update_on_path(&parent_path);
+                }
+                Undo::Tombstone(path, stamp) => {
+                    self.own_path(&path.0);
+                    if let AtPosition::Mini(Some(node)) =
+                        Self::traverse_node_at_pos_id(AtPosition::Major(self.root.clone()), &path.0)
                     {
-                        let node_mut = node.borrow_mut();
-                        node_mut.remove_mini(last.1)?;
-                        self.doc_length -= 1;
-                        return Ok(());
+                        node.borrow_mut().deleted.retain(|s| *s != stamp);
+                        let tombstone = !node.borrow().deleted.is_empty();
+                        node.borrow_mut().tombstone = tombstone;
+                        self.update_on_path(&path.0);
+                        if !self.moves.is_empty() {
+                            self.settle_moves(&path);
+                        }
                     }
                 }
-                return Err(Error::from(std::io::ErrorKind::InvalidInput));
+                Undo::Mark(id) => self.marks.retain(|sig| sig.id != id),
+                Undo::Move(id) => self.unapply_move(id),
             }
         }
+        self.doc_length = subtree_len(&self.root).chars;
     }
 
     // 0-index characters -> as supposed to indices pointing to "gaps" in the insertion
-    pub fn delete(&self, pos: usize) -> Result<DeleteSignal> {
-        let pos = if pos == 0 {
-            pos
-        } else if pos > self.doc_length {
-            self.doc_length - 1
-        } else {
-            pos - 1
-        };
+    pub fn delete(&mut self, pos: impl TextOffset) -> Result<DeleteSignal> {
+        let pos = pos.to_delete_index(self)?;
+        let pos_id = self.find_path_to_char(pos).unwrap_or_default();
+        // Like inserts, so edits made before applying either have their own stamps
+        self.lamport += 1;
         Ok(DeleteSignal {
-            pos_id: self.find_path_to_char(pos).unwrap_or(PosID::new()),
-            _unique_disambiguator: self.unique_disambiguator,
+            pos_id: CompactPosID::from(&pos_id),
+            unique_disambiguator: self.unique_disambiguator,
+            lamport: self.lamport,
         })
     }
 
-    pub fn insert(&mut self, pos: usize, ch: char) -> Result<InsertSignal> {
+    pub fn insert(&mut self, pos: impl TextOffset, atom: impl Into<Atom>) -> Result<InsertSignal> {
+        let pos = pos.to_char_index(self)?;
         if pos > self.doc_length {
             return Err(Error::from(std::io::ErrorKind::InvalidInput));
         }
@@ -168,17 +434,143 @@ impl Treedoc {
             self.find_path_to_char(pos)
                 .unwrap_or_else(PosID::new_empty_end)
         };
-        let new_pos_id = self.new_pos_id(&prev, &next);
+        let (new_pos_id, grow) = self.new_pos_id(&prev, &next);
+        self.lamport += 1;
 
         Ok(InsertSignal {
-            atom: ch,
-            pos_id: new_pos_id,
+            atom: atom.into(),
+            pos_id: CompactPosID::from(&new_pos_id),
             unique_disambiguator: self.unique_disambiguator,
+            lamport: self.lamport,
+            grow,
         })
     }
 
+    pub fn len_utf8(&self) -> usize {
+        subtree_len(&self.root).utf8
+    }
+
+    pub fn len_utf16(&self) -> usize {
+        subtree_len(&self.root).utf16
+    }
+
+    /// Converts any offset into a char offset; fails if it splits a char
+    pub fn to_char_offset(&self, offset: impl TextOffset) -> Result<CharOffset> {
+        Ok(CharOffset(offset.to_char_index(self)?))
+    }
+
+    /// Converts any offset into a UTF-8 byte offset
+    pub fn to_utf8_offset(&self, offset: impl TextOffset) -> Result<Utf8Offset> {
+        let (before, _) = self.locate(offset.to_char_index(self)?, Unit::Char)?;
+        Ok(Utf8Offset(before.utf8))
+    }
+
+    /// Converts any offset into a UTF-16 code unit offset
+    pub fn to_utf16_offset(&self, offset: impl TextOffset) -> Result<Utf16Offset> {
+        let (before, _) = self.locate(offset.to_char_index(self)?, Unit::Char)?;
+        Ok(Utf16Offset(before.utf16))
+    }
+
+    pub(crate) fn unit_to_char(&self, offset: usize, unit: Unit) -> Result<usize> {
+        self.locate(offset, unit).map(|(before, _)| before.chars)
+    }
+
     fn find_path_to_char(&self, target_index: usize) -> Option<PosID> {
-        self.iter().nth(target_index)
+        self.locate(target_index, Unit::Char).ok()?.1
+    }
+
+    // Descends by the subtree lengths to the atom starting at `target` (counted in `unit`).
+    // Returns the length of the text before it and its path, or no path at the end of the doc.
+    fn locate(&self, target: usize, unit: Unit) -> Result<(TextLength, Option<PosID>)> {
+        let total = subtree_len(&self.root);
+        if target >= total.get(unit) {
+            if target == total.get(unit) {
+                return Ok((total, None));
+            }
+            return Err(Error::from(std::io::ErrorKind::InvalidInput));
+        }
+        let mut remaining = target;
+        let mut before = TextLength::default();
+        let mut path = PosID::new();
+        let mut curr = self.root.clone();
+        while let Some(node) = curr {
+            let node = node.borrow();
+            let left = subtree_len(&node.left);
+            if remaining < left.get(unit) {
+                path.0.push(PathComponent(0, None));
+                curr = node.left.clone();
+                continue;
+            }
+            remaining -= left.get(unit);
+            before += left;
+            let mut descend = None;
+            for mini in node.children.borrow().iter() {
+                let mini = mini.borrow();
+                if remaining >= mini.len.get(unit) {
+                    remaining -= mini.len.get(unit);
+                    before += mini.len;
+                    continue;
+                }
+                path.0.push(PathComponent(0, Some(mini.disambiguator)));
+                let left = subtree_len(&mini.left);
+                if remaining < left.get(unit) {
+                    path.0.push(PathComponent(0, None));
+                    descend = Some(mini.left.clone());
+                    break;
+                }
+                remaining -= left.get(unit);
+                before += left;
+                let own = mini.atom_len();
+                if remaining < own.get(unit) {
+                    if remaining == 0 {
+                        return Ok((before, Some(path)));
+                    }
+                    // Offset points inside a char
+                    return Err(Error::from(std::io::ErrorKind::InvalidInput));
+                }
+                remaining -= own.get(unit);
+                before += own;
+                path.0.push(PathComponent(1, None));
+                descend = Some(mini.right.clone());
+                break;
+            }
+            curr = match descend {
+                Some(next) => next,
+                None => {
+                    path.0.push(PathComponent(1, None));
+                    node.right.clone()
+                }
+            };
+        }
+        Err(Error::from(std::io::ErrorKind::InvalidData))
+    }
+
+    // Lengths and hashes are cached per subtree, so every node on the path of a
+    // changed atom is refreshed bottom-up
+    pub(crate) fn update_on_path(&self, path: &[PathComponent]) {
+        let mut visited = Vec::new();
+        let mut at = AtPosition::Major(self.root.clone());
+        for path_comp in path {
+            let next = Self::step(&at, path_comp);
+            visited.push(at);
+            at = next;
+        }
+        visited.push(at);
+        for at in visited.iter().rev() {
+            match at {
+                AtPosition::Major(Some(node)) => {
+                    let mut node = node.borrow_mut();
+                    node.update_len();
+                    node.update_hash();
+                }
+                AtPosition::Mini(Some(mini)) => {
+                    let mut mini = mini.borrow_mut();
+                    mini.update_len();
+                    mini.update_hash();
+                }
+                _ => {}
+            }
+        }
     }
     /*
     "A major node is ordered by infix-order
@@ -191,8 +583,8 @@ impl Treedoc {
             Self::traverse_in_and_collect(&node.borrow().left, vec);
             for mininode in node.borrow().children.borrow().iter() {
                 Self::traverse_in_and_collect(&mininode.borrow().left, vec);
-                if !mininode.borrow().tombstone {
-                    vec.push(mininode.borrow().atom);
+                if mininode.borrow().is_visible() {
+                    vec.push(mininode.borrow().atom.clone());
                 }
                 Self::traverse_in_and_collect(&mininode.borrow().right, vec);
             }
@@ -200,127 +592,464 @@ impl Treedoc {
         }
     }
 
-    fn new_pos_id(&mut self, prev: &PosID, next: &PosID) -> PosID {
-        if prev.0.len() < next.0.len() && next.0.starts_with(&prev.0) {
-            let mut f_prev = next.clone();
-            f_prev.0.pop();
-            f_prev.0.push(PathComponent(0, None));
-            return f_prev;
-        } else if next.0.len() < prev.0.len() && prev.0.starts_with(&next.0) {
-            let mut p_prev = prev.clone();
-            p_prev.0.pop();
-            p_prev.0.push(PathComponent(1, None));
-            return p_prev;
-        } else if let Some(p) = prev.0.split_last() {
-            if let Some(f) = next.0.split_last() {
-                // Minisiblings
-                if p.1 == f.1 && p.0 != f.0 && !p.1.is_empty() {
-                    let mut p_prev = prev.clone();
-                    p_prev
-                        .0
-                        .push(PathComponent(0, Some(self.unique_disambiguator)));
-                    return p_prev;
-                }
+    /// Lazily walks the visible atoms; `skip`/`nth` seek by subtree lengths
+    pub fn atoms(&self) -> Atoms<'_> {
+        Atoms::new(self)
+    }
+
+    /// Like `atoms`, but also yields the PosID of every atom
+    pub fn iter_with_ids(&self) -> AtomsWithIds<'_> {
+        AtomsWithIds::new(self)
+    }
+
+    pub fn char_at(&self, pos: usize) -> Option<Atom> {
+        self.atoms().nth(pos)
+    }
+
+    /// Char range of the visible text; bounds past the end are clamped
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> String {
+        let start = match range.start_bound() {
+            Bound::Included(&s) => s,
+            Bound::Excluded(&s) => s + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&e) => e + 1,
+            Bound::Excluded(&e) => e,
+            Bound::Unbounded => self.doc_length,
+        };
+        self.atoms()
+            .skip(start)
+            .take(end.min(self.doc_length).saturating_sub(start))
+            .collect()
+    }
+
+    // Picks a free slot right after `prev` or right before `next`, preferring a
+    // new major node next to the one holding the atom. Only empty slots are handed
+    // out: an insert must never land on an existing node, unless it is one left
+    // empty for it by balanced allocation. Also returns how many empty major
+    // nodes the insert creates above the atom.
+    fn new_pos_id(&mut self, prev: &PosID, next: &PosID) -> (PosID, usize) {
+        if let Some((major, mini)) = self.resolve_atom(prev) {
+            let mini = mini.borrow();
+            if mini.right.is_none() {
+                let (_, major_path) = prev.0.split_last().unwrap();
+                let major = major.borrow();
+                let is_last = major
+                    .children
+                    .borrow()
+                    .last()
+                    .map(|m| m.borrow().disambiguator)
+                    == Some(mini.disambiguator);
+                let mut pos_id = if is_last && major.right.is_none() {
+                    if self.allocation == Allocation::Balanced {
+                        return self.balanced_append(major_path, &major);
+                    }
+                    PosID(major_path.to_vec())
+                } else {
+                    prev.clone()
+                };
+                pos_id.0.push(PathComponent(1, None));
+                return (pos_id, 0);
             }
         }
-        let mut p_prev = prev.strip_to_major();
-        let mut p_next = next.strip_to_major();
-        p_prev.0.push(PathComponent(1, None));
-        if p_prev.0 < p_next.0 {
-            return p_prev;
+        if let Some((major, mini)) = self.resolve_atom(next) {
+            let mini = mini.borrow();
+            if mini.left.is_none() {
+                let (_, major_path) = next.0.split_last().unwrap();
+                let major = major.borrow();
+                let is_first = major
+                    .children
+                    .borrow()
+                    .first()
+                    .map(|m| m.borrow().disambiguator)
+                    == Some(mini.disambiguator);
+                let mut pos_id = if is_first && major.left.is_none() {
+                    PosID(major_path.to_vec())
+                } else {
+                    next.clone()
+                };
+                pos_id.0.push(PathComponent(0, None));
+                return (pos_id, 0);
+            }
+        }
+        // Both neighbours are taken, so prev has a right subtree (or prev is the
+        // start of the doc) and its leftmost gap is right after prev
+        let (mut pos_id, mut node) = match self.resolve_atom(prev) {
+            Some((_, mini)) => {
+                let mut pos_id = prev.clone();
+                pos_id.0.push(PathComponent(1, None));
+                (pos_id, mini.borrow().right.clone())
+            }
+            None => (PosID::new(), self.root.clone()),
+        };
+        while let Some(major) = node {
+            pos_id.0.push(PathComponent(0, None));
+            node = major.borrow().left.clone();
+        }
+        if pos_id.0.is_empty() {
+            pos_id.0.push(PathComponent(0, None));
+        }
+        (pos_id, 0)
+    }
+
+    // The major node and mininode of an atom's PosID; None for the start/end sentinels
+    fn resolve_atom(&self, pos_id: &PosID) -> Option<AtomNodes> {
+        let (last, major_path) = pos_id.0.split_last()?;
+        last.1?;
+        if major_path.iter().any(|c| c.0 > 1) {
+            return None;
+        }
+        match Self::traverse_node_at_pos_id(
+            AtPosition::Major(self.root.clone()),
+            &major_path.to_vec(),
+        ) {
+            AtPosition::Major(Some(major)) => {
+                let mini = major
+                    .borrow()
+                    .children
+                    .borrow()
+                    .iter()
+                    .find(|m| Some(m.borrow().disambiguator) == last.1)
+                    .cloned()?;
+                Some((major, mini))
+            }
+            _ => None,
+        }
+    }
+
+    fn add_unique_mini(node: &Rc<RefCell<Node>>, mini: Mininode) -> Result<()> {
+        let node = node.borrow();
+        if node
+            .children
+            .borrow()
+            .iter()
+            .any(|m| m.borrow().disambiguator == mini.disambiguator)
+        {
+            return Err(Error::from(std::io::ErrorKind::AlreadyExists));
         }
-        drop(p_prev);
-        p_next.0.push(PathComponent(0, None));
-        return p_next;
+        node.add_mini(mini);
+        Ok(())
     }
 
-    fn traverse_node_at_pos_id(node: AtPosition, curr_pos_id: &Vec<PathComponent>) -> AtPosition {
+    pub(crate) fn traverse_node_at_pos_id(
+        node: AtPosition,
+        curr_pos_id: &Vec<PathComponent>,
+    ) -> AtPosition {
         let mut ref_point = node.clone();
         for path_comp in curr_pos_id {
-            match ref_point {
-                AtPosition::Major(major) => {
-                    match (path_comp.0, path_comp.1) {
-                        (0, None) => {
-                            ref_point = AtPosition::Major(major.unwrap().borrow().left.clone())
-                        }
-                        (1, None) => {
-                            ref_point = AtPosition::Major(major.unwrap().borrow().right.clone())
-                        }
-                        (0, Some(dis)) => {
-                            ref_point = AtPosition::Mini(
-                                major
-                                    .unwrap()
-                                    .borrow()
-                                    .children
-                                    .borrow()
-                                    .iter()
-                                    .find(|mn| mn.borrow().disambiguator == dis)
-                                    .cloned(),
-                            );
-                        }
-                        _ => unreachable!(),
-                    };
+            ref_point = Self::step(&ref_point, path_comp);
+        }
+        ref_point
+    }
+
+    // One path component down from `at`; stepping off the tree yields a None position
+    fn step(at: &AtPosition, path_comp: &PathComponent) -> AtPosition {
+        match at {
+            AtPosition::Major(major) => {
+                let major = major.as_ref().map(|m| m.borrow());
+                match (path_comp.0, path_comp.1) {
+                    (0, None) => AtPosition::Major(major.and_then(|m| m.left.clone())),
+                    (1, None) => AtPosition::Major(major.and_then(|m| m.right.clone())),
+                    (0, Some(dis)) => AtPosition::Mini(major.and_then(|m| {
+                        m.children
+                            .borrow()
+                            .iter()
+                            .find(|mn| mn.borrow().disambiguator == dis)
+                            .cloned()
+                    })),
+                    // Not a valid step, treat it as leaving the tree
+                    _ => AtPosition::Major(None),
                 }
-                AtPosition::Mini(mini) => {
-                    match (path_comp.0, path_comp.1) {
-                        (0, None) => {
-                            ref_point = AtPosition::Major(mini.unwrap().borrow().left.clone())
-                        }
-                        (1, None) => {
-                            ref_point = AtPosition::Major(mini.unwrap().borrow().right.clone())
-                        }
-                        _ => unreachable!(),
-                    };
+            }
+            AtPosition::Mini(mini) => {
+                let mini = mini.as_ref().map(|m| m.borrow());
+                match (path_comp.0, path_comp.1) {
+                    (0, None) => AtPosition::Major(mini.and_then(|m| m.left.clone())),
+                    (1, None) => AtPosition::Major(mini.and_then(|m| m.right.clone())),
+                    _ => AtPosition::Major(None),
                 }
             }
         }
-        ref_point
     }
 
-    fn iterate_pos_id(
-        node: &Option<Rc<RefCell<Node>>>,
-        curr_path: &mut PosID,
-        iterated_vec: &mut VecDeque<PosID>,
+    /// PosIDs of the visible atoms in document order
+    pub fn iter(&self) -> TreedocIter<'_> {
+        TreedocIter::new(self)
+    }
+
+    /// Visible index of the atom with this PosID, following its moves
+    pub fn pos_of(&self, pos_id: &PosID) -> Option<usize> {
+        self.visible_index(&self.current_path(pos_id))
+    }
+
+    /// The atom with this PosID, unless it has been deleted
+    pub fn atom_at_id(&self, pos_id: &PosID) -> Option<Atom> {
+        match self.rank(&self.current_path(pos_id)) {
+            (_, Some(mini)) if mini.borrow().is_visible() => Some(mini.borrow().atom.clone()),
+            _ => None,
+        }
+    }
+
+    // Index of the mininode at exactly this path, if it is shown
+    pub(crate) fn visible_index(&self, path: &PosID) -> Option<usize> {
+        match self.rank(path) {
+            (idx, Some(mini)) if mini.borrow().is_visible() => Some(idx),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn mini_at(&self, path: &PosID) -> Option<Rc<RefCell<Mininode>>> {
+        match Self::traverse_node_at_pos_id(AtPosition::Major(self.root.clone()), &path.0) {
+            AtPosition::Mini(mini) => mini,
+            AtPosition::Major(_) => None,
+        }
+    }
+
+    /// Signals rebuilding this document, tombstones and marks included, when applied
+    /// in order to an empty one
+    pub fn snapshot(&self) -> Vec<Signal> {
+        let mut out = Vec::new();
+        // Slots of moves are rebuilt by the moves themselves
+        let slots: HashMap<CompactPosID, &MoveSignal> = self
+            .moves
+            .iter()
+            .map(|m| (CompactPosID::from(&m.slot.atom_path()), m))
+            .collect();
+        if let Some(root) = &self.root {
+            Self::snapshot_major(root, &mut PosID::new(), 0, &slots, &mut out);
+        }
+        out.extend(self.marks.iter().cloned().map(Signal::Mark));
+        out
+    }
+
+    // Parents go before their children so every insert finds its parent. The
+    // `empty` major nodes right above `node` are created by its first insert.
+    fn snapshot_major(
+        node: &Rc<RefCell<Node>>,
+        path: &mut PosID,
+        empty: usize,
+        slots: &HashMap<CompactPosID, &MoveSignal>,
+        out: &mut Vec<Signal>,
     ) {
-        if let Some(node) = node {
-            curr_path.0.push(PathComponent(0, None));
-            Self::iterate_pos_id(&node.borrow().left, curr_path, iterated_vec);
-            curr_path.0.pop();
-            for mininode in node.borrow().children.borrow().iter() {
-                curr_path
-                    .0
-                    .push(PathComponent(0, Some(mininode.borrow().disambiguator)));
-                curr_path.0.push(PathComponent(0, None));
-                Self::iterate_pos_id(&mininode.borrow().left, curr_path, iterated_vec);
-                curr_path.0.pop();
-                curr_path.0.pop();
-                if !mininode.borrow().tombstone {
-                    let mut final_path = curr_path.clone();
-                    final_path
-                        .0
-                        .push(PathComponent(0, Some(mininode.borrow().disambiguator)));
-                    iterated_vec.push_back(final_path);
+        let node = node.borrow();
+        for (i, mini) in node.children.borrow().iter().enumerate() {
+            let mini = mini.borrow();
+            // The first mini of a non-root node creates the node itself
+            let pos_id = if i == 0 && !path.0.is_empty() {
+                path.clone()
+            } else {
+                PosID(
+                    [
+                        path.0.as_slice(),
+                        &[PathComponent(0, Some(mini.disambiguator))],
+                    ]
+                    .concat(),
+                )
+            };
+            let insert = InsertSignal {
+                atom: mini.atom.clone(),
+                pos_id: CompactPosID::from(&pos_id),
+                unique_disambiguator: mini.disambiguator,
+                lamport: mini.inserted.0,
+                grow: if i == 0 { empty } else { 0 },
+            };
+            path.0.push(PathComponent(0, Some(mini.disambiguator)));
+            let atom_path = CompactPosID::from(&*path);
+            let slot = slots.get(&atom_path);
+            match slot {
+                Some(op) => out.push(Signal::Move(MoveSignal {
+                    slot: insert,
+                    ..(*op).clone()
+                })),
+                None => out.push(Signal::Insert(insert)),
+            }
+            // Slots take their tombstone from the moved atom
+            for &(lamport, site) in mini.deleted.iter().filter(|_| slot.is_none()) {
+                out.push(Signal::Delete(DeleteSignal {
+                    pos_id: atom_path.clone(),
+                    unique_disambiguator: site,
+                    lamport,
+                }));
+            }
+            for (dir, child) in [(0, &mini.left), (1, &mini.right)] {
+                if let Some(child) = child {
+                    path.0.push(PathComponent(dir, None));
+                    Self::snapshot_major(child, path, 0, slots, out);
+                    path.0.pop();
                 }
-                curr_path
-                    .0
-                    .push(PathComponent(0, Some(mininode.borrow().disambiguator)));
-                curr_path.0.push(PathComponent(1, None));
-                Self::iterate_pos_id(&mininode.borrow().right, curr_path, iterated_vec);
-                curr_path.0.pop();
-                curr_path.0.pop();
             }
-            curr_path.0.push(PathComponent(1, None));
-            Self::iterate_pos_id(&node.borrow().right, curr_path, iterated_vec);
-            curr_path.0.pop();
+            path.0.pop();
         }
+        // The root always exists
+        let empty = if node.children.borrow().is_empty() && !path.0.is_empty() {
+            empty + 1
+        } else {
+            0
+        };
+        for (dir, child) in [(0, &node.left), (1, &node.right)] {
+            if let Some(child) = child {
+                path.0.push(PathComponent(dir, None));
+                Self::snapshot_major(child, path, empty, slots, out);
+                path.0.pop();
+            }
+        }
+    }
+
+    /// Merkle hash of the tree: PosIDs, atoms, tombstones and who made them
+    /// when, but not marks.
+    /// Replicas that applied the same inserts and deletes have the same hash.
+    pub fn root_hash(&self) -> u64 {
+        subtree_hash(&self.root)
     }
 
-    fn iter(&self) -> TreedocIter {
-        let mut iterated_vec: VecDeque<PosID> = VecDeque::new();
+    /// Hashes of the subtrees one step below `pos_id`, keyed by that step; empty
+    /// if nothing is there. Comparing these level by level from the root finds
+    /// where two replicas differ in as many round trips as the tree is deep.
+    pub fn child_hashes(&self, pos_id: &PosID) -> Vec<(PathComponent, u64)> {
+        let at = Self::traverse_node_at_pos_id(AtPosition::Major(self.root.clone()), &pos_id.0);
+        Self::children_of(&at)
+            .into_iter()
+            .map(|(step, _, hash)| (step, hash))
+            .collect()
+    }
 
-        Self::iterate_pos_id(&self.root, &mut PosID::new(), &mut iterated_vec);
-        TreedocIter {
-            paths: iterated_vec,
+    /// PosIDs where this replica and `other` disagree: atoms whose content,
+    /// tombstone or edit stamps differ, and the roots of subtrees only one side
+    /// has. Branches with equal hashes are skipped without being walked.
+    pub fn diff(&self, other: &Treedoc) -> Vec<PosID> {
+        let mut out = Vec::new();
+        if self.root_hash() != other.root_hash() {
+            Self::diff_at(
+                &AtPosition::Major(self.root.clone()),
+                &AtPosition::Major(other.root.clone()),
+                &mut PosID::new(),
+                &mut out,
+            );
+        }
+        out
+    }
+
+    fn diff_at(ours: &AtPosition, theirs: &AtPosition, path: &mut PosID, out: &mut Vec<PosID>) {
+        if let (AtPosition::Mini(Some(a)), AtPosition::Mini(Some(b))) = (ours, theirs) {
+            let (a, b) = (a.borrow(), b.borrow());
+            if a.atom != b.atom
+                || a.tombstone != b.tombstone
+                || a.inserted != b.inserted
+                || a.deleted != b.deleted
+            {
+                out.push(path.clone());
+            }
+        }
+        let ours = Self::children_of(ours);
+        let theirs = Self::children_of(theirs);
+        for (step, child, hash) in &ours {
+            path.0.push(step.clone());
+            match theirs.iter().find(|(s, _, _)| s == step) {
+                Some((_, _, their_hash)) if their_hash == hash => {}
+                Some((_, their_child, _)) => Self::diff_at(child, their_child, path, out),
+                None => out.push(path.clone()),
+            }
+            path.0.pop();
+        }
+        for (step, _, _) in &theirs {
+            if !ours.iter().any(|(s, _, _)| s == step) {
+                out.push(PosID(
+                    [path.0.as_slice(), std::slice::from_ref(step)].concat(),
+                ));
+            }
+        }
+    }
+
+    // Left subtree, mininodes and right subtree of a major node; left and right
+    // subtree of a mininode
+    fn children_of(at: &AtPosition) -> Vec<Child> {
+        let major = |dir, node: &Option<Rc<RefCell<Node>>>| {
+            node.as_ref().map(|n| {
+                (
+                    PathComponent(dir, None),
+                    AtPosition::Major(Some(n.clone())),
+                    n.borrow().hash,
+                )
+            })
+        };
+        match at {
+            AtPosition::Major(Some(node)) => {
+                let node = node.borrow();
+                let mut children: Vec<Child> = major(0, &node.left).into_iter().collect();
+                for mini in node.children.borrow().iter() {
+                    children.push((
+                        PathComponent(0, Some(mini.borrow().disambiguator)),
+                        AtPosition::Mini(Some(mini.clone())),
+                        mini.borrow().hash,
+                    ));
+                }
+                children.extend(major(1, &node.right));
+                children
+            }
+            AtPosition::Mini(Some(mini)) => {
+                let mini = mini.borrow();
+                major(0, &mini.left)
+                    .into_iter()
+                    .chain(major(1, &mini.right))
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // Follows the path counting the visible atoms ordered before it. Also works for
+    // PosIDs that are no longer (or not yet) in the tree: the walk stops where the
+    // node would be, so the count is still the index it would take.
+    pub(crate) fn rank(&self, pos_id: &PosID) -> (usize, Option<Rc<RefCell<Mininode>>>) {
+        let mut count = 0;
+        let mut at = AtPosition::Major(self.root.clone());
+        for path_comp in &pos_id.0 {
+            at = match (at, path_comp.0, path_comp.1) {
+                (AtPosition::Major(Some(node)), dir, None) => {
+                    let node = node.borrow();
+                    if dir == 0 {
+                        AtPosition::Major(node.left.clone())
+                    } else {
+                        count += subtree_len(&node.left).chars;
+                        for mini in node.children.borrow().iter() {
+                            count += mini.borrow().len.chars;
+                        }
+                        AtPosition::Major(node.right.clone())
+                    }
+                }
+                (AtPosition::Major(Some(node)), _, Some(dis)) => {
+                    let node = node.borrow();
+                    count += subtree_len(&node.left).chars;
+                    let mut found = None;
+                    for mini in node.children.borrow().iter() {
+                        let mini_ref = mini.borrow();
+                        if mini_ref.disambiguator < dis {
+                            count += mini_ref.len.chars;
+                        } else if mini_ref.disambiguator == dis {
+                            found = Some(mini.clone());
+                        }
+                    }
+                    AtPosition::Mini(found)
+                }
+                (AtPosition::Mini(Some(mini)), dir, None) => {
+                    let mini = mini.borrow();
+                    if dir == 0 {
+                        AtPosition::Major(mini.left.clone())
+                    } else {
+                        count += subtree_len(&mini.left).chars + mini.atom_len().chars;
+                        AtPosition::Major(mini.right.clone())
+                    }
+                }
+                _ => return (count, None),
+            };
+        }
+        match at {
+            AtPosition::Mini(Some(mini)) => {
+                count += subtree_len(&mini.borrow().left).chars;
+                (count, Some(mini))
+            }
+            _ => (count, None),
         }
     }
 }
@@ -345,9 +1074,37 @@ mod tests {
             Treedoc::traverse_node_at_pos_id(AtPosition::Major(td.root.clone()), &pos_id_root.0);
         if let AtPosition::Mini(mn) = a_node {
             assert!(mn.is_some());
-            assert!(mn.unwrap().borrow().atom == 'b');
+            assert!(mn.unwrap().borrow().atom == Atom::Char('b'));
         } else {
             panic!("Wrong node type iterated")
         }
     }
+
+    #[test]
+    fn test_find_path_matches_iter() {
+        let mut td = Treedoc::new('a');
+        for (pos, ch) in [(1, 'c'), (2, 'e'), (1, 'b'), (3, 'd'), (5, 'f')] {
+            let sig = td.insert(pos, ch).unwrap();
+            td.apply(Signal::Insert(sig)).unwrap();
+        }
+        let sig = td.delete(2).unwrap();
+        td.apply(Signal::Delete(sig)).unwrap();
+
+        for (idx, pos_id) in td.iter().enumerate() {
+            assert_eq!(td.pos_of(&pos_id), Some(idx));
+            assert_eq!(td.find_path_to_char(idx), Some(pos_id));
+        }
+        assert_eq!(td.find_path_to_char(td.doc_length), None);
+
+        // The recursive walk doesn't seek by subtree lengths, so it checks them
+        let mut atoms = Vec::new();
+        Treedoc::traverse_in_and_collect(&td.root, &mut atoms);
+        assert_eq!(atoms.len(), td.doc_length);
+        let with_ids: Vec<(PosID, Atom)> = td.iter_with_ids().collect();
+        for (idx, atom) in atoms.into_iter().enumerate() {
+            let pos_id = td.find_path_to_char(idx).unwrap();
+            assert_eq!(td.mini_at(&pos_id).unwrap().borrow().atom, atom);
+            assert_eq!(with_ids[idx], (pos_id, atom));
+        }
+    }
 }
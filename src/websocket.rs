@@ -0,0 +1,296 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    io::{Error, ErrorKind, Read, Result, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use crate::sync::{MAX_FRAME_LEN, Received};
+
+/*
+Just enough RFC 6455 for browsers to talk to the relay: the HTTP upgrade
+handshake, binary messages (fragmented ones are reassembled), ping/pong and close.
+Each message carries one encoded relay message, the same bytes a TCP frame would.
+No extensions or subprotocols are negotiated.
+*/
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_HTTP_HEAD: usize = 8 << 10;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// The Sec-WebSocket-Accept answer to a Sec-WebSocket-Key
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+/// Answers a client's upgrade request, or sends a 400 and fails
+pub(crate) fn accept_handshake(stream: &mut TcpStream, timeout: Duration) -> Result<()> {
+    let head = read_http_head(stream, timeout)?;
+    let mut lines = head.split("\r\n");
+    let is_get = lines.next().is_some_and(|line| line.starts_with("GET "));
+    let headers: Vec<(String, &str)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim()))
+        .collect();
+    let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| *v);
+
+    let upgrade = header("upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    match header("sec-websocket-key") {
+        Some(key) if is_get && upgrade => {
+            write!(
+                stream,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)
+            )?;
+            stream.flush()
+        }
+        _ => {
+            stream.write_all(
+                b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+            )?;
+            Err(Error::from(ErrorKind::InvalidData))
+        }
+    }
+}
+
+/// Upgrades a fresh connection to `host` as a client
+pub(crate) fn client_handshake(
+    stream: &mut TcpStream,
+    host: &str,
+    path: &str,
+    timeout: Duration,
+) -> Result<()> {
+    let key = base64(&random_u64().to_le_bytes().repeat(2));
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: {host}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    let head = read_http_head(stream, timeout)?;
+    let mut lines = head.split("\r\n");
+    let switched = lines
+        .next()
+        .is_some_and(|line| line.split(' ').nth(1) == Some("101"));
+    let accepted = lines
+        .filter_map(|line| line.split_once(':'))
+        .any(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("sec-websocket-accept")
+                && value.trim() == accept_key(&key)
+        });
+    if !(switched && accepted) {
+        return Err(Error::from(ErrorKind::InvalidData));
+    }
+    Ok(())
+}
+
+// Reads byte by byte so nothing after the blank line gets consumed. `timeout`
+// bounds the whole head, so a peer sending it slowly, or not at all, doesn't
+// keep the thread reading from it.
+fn read_http_head(stream: &mut TcpStream, timeout: Duration) -> Result<String> {
+    let deadline = Instant::now() + timeout;
+    let mut head = Vec::new();
+    let mut byte = [0u8];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HTTP_HEAD {
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(Error::from(ErrorKind::TimedOut));
+        }
+        stream.set_read_timeout(Some(left))?;
+        stream.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    stream.set_read_timeout(None)?;
+    String::from_utf8(head).map_err(|_| Error::from(ErrorKind::InvalidData))
+}
+
+/// Sends one binary message. Clients have to mask what they send.
+pub(crate) fn write_message(stream: &mut impl Write, payload: &[u8], masked: bool) -> Result<()> {
+    write_ws_frame(stream, OP_BINARY, payload, masked)
+}
+
+pub(crate) fn write_close(stream: &mut impl Write, masked: bool) -> Result<()> {
+    write_ws_frame(stream, OP_CLOSE, &[], masked)
+}
+
+pub(crate) fn write_pong(stream: &mut impl Write, payload: &[u8], masked: bool) -> Result<()> {
+    write_ws_frame(stream, OP_PONG, payload, masked)
+}
+
+fn write_ws_frame(stream: &mut impl Write, opcode: u8, payload: &[u8], masked: bool) -> Result<()> {
+    let mut buf = Vec::with_capacity(payload.len() + 14);
+    buf.push(0x80 | opcode);
+    let mask_bit = if masked { 0x80 } else { 0 };
+    match payload.len() {
+        len @ 0..=125 => buf.push(mask_bit | len as u8),
+        len @ 126..=0xFFFF => {
+            buf.push(mask_bit | 126);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            buf.push(mask_bit | 127);
+            buf.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    if masked {
+        let key = (random_u64() as u32).to_be_bytes();
+        buf.extend_from_slice(&key);
+        buf.extend(payload.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+    } else {
+        buf.extend_from_slice(payload);
+    }
+    stream.write_all(&buf)?;
+    stream.flush()
+}
+
+/// Reads frames until a whole message or a ping arrives. `partial` holds the
+/// fragments of a message interrupted by a ping and must be kept between calls.
+pub(crate) fn read_message(
+    stream: &mut impl Read,
+    from_client: bool,
+    partial: &mut Option<Vec<u8>>,
+) -> Result<Received> {
+    loop {
+        let mut head = [0u8; 2];
+        stream.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        // Clients must mask their frames, servers must not. No extensions, so no RSV bits.
+        if (head[1] & 0x80 != 0) != from_client || head[0] & 0x70 != 0 {
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        let buffered = partial.as_ref().map_or(0, Vec::len) as u64;
+        if len.saturating_add(buffered) > MAX_FRAME_LEN as u64 {
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+        let mut key = [0u8; 4];
+        if from_client {
+            stream.read_exact(&mut key)?;
+        }
+        let mut payload = vec![0u8; len as usize];
+        stream.read_exact(&mut payload)?;
+        if from_client {
+            for (i, b) in payload.iter_mut().enumerate() {
+                *b ^= key[i % 4];
+            }
+        }
+
+        let data = match opcode {
+            OP_CLOSE => return Ok(Received::Close),
+            OP_PING | OP_PONG if !fin || len > 125 => {
+                return Err(Error::from(ErrorKind::InvalidData));
+            }
+            OP_PING => return Ok(Received::Ping(payload)),
+            OP_PONG => continue,
+            OP_TEXT | OP_BINARY if partial.is_none() => payload,
+            OP_CONTINUATION if partial.is_some() => {
+                let mut data = partial.take().unwrap();
+                data.extend_from_slice(&payload);
+                data
+            }
+            _ => return Err(Error::from(ErrorKind::InvalidData)),
+        };
+        if fin {
+            return Ok(Received::Data(data));
+        }
+        *partial = Some(data);
+    }
+}
+
+// Only for handshake keys and frame masks, which need not be unguessable
+fn random_u64() -> u64 {
+    RandomState::new().hash_one(0u8)
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in msg.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0u8; 20];
+    for (bytes, v) in out.chunks_exact_mut(4).zip(h) {
+        bytes.copy_from_slice(&v.to_be_bytes());
+    }
+    out
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,117 @@
+// Fixtures shared by the integration tests and the benchmarks. Every test
+// crate uses only some of them.
+#![allow(dead_code)]
+
+#[cfg(feature = "sync")]
+use std::time::Instant;
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+#[cfg(feature = "sync")]
+use simple_text_crdt::relay::{RelayClient, RelayServer};
+use simple_text_crdt::{
+    events::Change,
+    offset::CharOffset,
+    treedoc::{Signal, Treedoc},
+};
+
+/// How long the network tests wait for anything before failing
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// xorshift64, so runs with the same seed make the same edits
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n.max(1) as u64) as usize
+    }
+
+    /// Lowercase words
+    pub fn text(&mut self, len: usize) -> String {
+        (0..len)
+            .map(|_| match self.below(8) {
+                0 => ' ',
+                _ => (b'a' + self.below(26) as u8) as char,
+            })
+            .collect()
+    }
+}
+
+/// An empty document edited as `site`
+pub fn site(site: u64) -> Treedoc {
+    Treedoc {
+        unique_disambiguator: site,
+        ..Treedoc::default()
+    }
+}
+
+/// Applies a signal made on `td` and hands it back to send elsewhere
+pub fn edit(td: &mut Treedoc, sig: Signal) -> Signal {
+    td.apply(sig.clone()).unwrap();
+    sig
+}
+
+/// The visible text of `td`
+pub fn text(td: &Treedoc) -> String {
+    td.atoms().collect()
+}
+
+pub fn insert(td: &mut Treedoc, pos: usize, ch: char) -> Signal {
+    let sig = Signal::Insert(td.insert(pos, ch).unwrap());
+    edit(td, sig)
+}
+
+pub fn type_at(td: &mut Treedoc, pos: usize, text: &str) -> Vec<Signal> {
+    text.chars()
+        .enumerate()
+        .map(|(i, ch)| insert(td, pos + i, ch))
+        .collect()
+}
+
+/// Collects every change `td` reports from now on
+pub fn record(td: &mut Treedoc) -> Rc<RefCell<Vec<Change>>> {
+    let changes = Rc::new(RefCell::new(Vec::new()));
+    let sink = changes.clone();
+    td.subscribe(move |change| sink.borrow_mut().push(change.clone()));
+    changes
+}
+
+/// Deletes `len` chars from `pos` on, one signal each
+pub fn delete(td: &mut Treedoc, pos: usize, len: usize) -> Vec<Signal> {
+    (0..len)
+        .map(|_| {
+            let sig = Signal::Delete(td.delete(CharOffset(pos)).unwrap());
+            edit(td, sig)
+        })
+        .collect()
+}
+
+// Server and clients share the test thread, so both sides get polled until the join completes
+#[cfg(feature = "sync")]
+pub fn wait_joined(server: &mut RelayServer, client: &mut RelayClient) {
+    let deadline = Instant::now() + TIMEOUT;
+    while !client.is_joined() {
+        server.poll_timeout(Duration::from_millis(5)).unwrap();
+        client.poll().unwrap();
+        assert!(Instant::now() < deadline, "join timed out");
+    }
+}
+
+/// Polls until every client has seen everything the room ordered
+#[cfg(feature = "sync")]
+pub fn settle(server: &mut RelayServer, room: &str, clients: &mut [&mut RelayClient]) {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        server.poll_timeout(Duration::from_millis(5)).unwrap();
+        for client in clients.iter_mut() {
+            client.poll().unwrap();
+        }
+        let seq = server.seq(room).unwrap();
+        if clients.iter().all(|c| c.is_synced() && c.seq() == seq) {
+            return;
+        }
+        assert!(Instant::now() < deadline, "clients did not catch up");
+    }
+}
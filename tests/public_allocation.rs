@@ -0,0 +1,110 @@
+use std::io::ErrorKind;
+
+use simple_text_crdt::{
+    allocation::Allocation,
+    encoding::{from_bytes, to_bytes},
+    offset::CharOffset,
+    treedoc::{Signal, Treedoc},
+};
+
+fn balanced(site: u64) -> Treedoc {
+    Treedoc {
+        unique_disambiguator: site,
+        allocation: Allocation::Balanced,
+        ..Treedoc::default()
+    }
+}
+
+fn type_at_end(td: &mut Treedoc, text: &str) -> Vec<Signal> {
+    text.chars()
+        .map(|ch| {
+            let sig = Signal::Insert(td.insert(td.doc_length, ch).unwrap());
+            td.apply(sig.clone()).unwrap();
+            sig
+        })
+        .collect()
+}
+
+#[test]
+fn test_balanced_typing_is_logarithmic() {
+    let text: String = (0..1024).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+    let mut simple = Treedoc::default();
+    type_at_end(&mut simple, &text);
+    let mut td = balanced(1);
+    let sigs = type_at_end(&mut td, &text);
+    assert_eq!(td.to_string(), text);
+
+    // log2(1024) = 10
+    let stats = td.stats();
+    assert!(stats.avg_pos_id_len <= 2.0 * 10.0 + 3.0, "{stats:?}");
+    assert!(stats.depth <= 2 * 10 + 3, "{stats:?}");
+    assert!(simple.stats().avg_pos_id_len > 300.0);
+
+    // Replicas allocating the simple way still understand the PosIDs
+    let mut remote = Treedoc::default();
+    for sig in sigs {
+        remote.apply(from_bytes(&to_bytes(&sig)).unwrap()).unwrap();
+    }
+    assert_eq!(remote.to_string(), text);
+    assert_eq!(remote.root_hash(), td.root_hash());
+}
+
+#[test]
+fn test_balanced_mixed_edits_converge() {
+    let mut a = balanced(1);
+    let mut b = balanced(2);
+    let sigs = type_at_end(&mut a, "hello world");
+    for sig in sigs {
+        b.apply(sig).unwrap();
+    }
+    // Both keep typing at the end at the same time, and edit in between
+    let mut from_a = type_at_end(&mut a, " from a");
+    let mut from_b = type_at_end(&mut b, " from b");
+    let sig = Signal::Delete(a.delete(CharOffset(0)).unwrap());
+    a.apply(sig.clone()).unwrap();
+    from_a.push(sig);
+    let sig = Signal::Insert(b.insert(5, ',').unwrap());
+    b.apply(sig.clone()).unwrap();
+    from_b.push(sig);
+    for sig in from_a {
+        b.apply(sig).unwrap();
+    }
+    for sig in from_b {
+        a.apply(sig).unwrap();
+    }
+    assert_eq!(a.to_string(), b.to_string());
+    assert_eq!(a.root_hash(), b.root_hash());
+
+    // Snapshots recreate the empty nodes the atoms hang from
+    let mut rebuilt = Treedoc::default();
+    for sig in a.snapshot() {
+        rebuilt.apply(sig).unwrap();
+    }
+    assert_eq!(rebuilt.root_hash(), a.root_hash());
+    assert_eq!(rebuilt.stats(), a.stats());
+}
+
+#[test]
+fn test_pre_grown_nodes_are_reverted() {
+    let mut td = balanced(1);
+    type_at_end(&mut td, "abcdef");
+    let hash = td.root_hash();
+    let stats = td.stats();
+
+    // Deleting what this replica does not have fails the transaction after an
+    // insert that pre-grows nodes
+    let mut other = balanced(2);
+    type_at_end(&mut other, "xyz");
+    let insert = Signal::Insert(td.insert(6, 'g').unwrap());
+    let delete = Signal::Delete(other.delete(CharOffset(2)).unwrap());
+    let err = td
+        .apply(Signal::Transaction(vec![insert.clone(), delete]))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert_eq!(td.root_hash(), hash);
+    assert_eq!(td.stats(), stats);
+
+    td.apply(insert).unwrap();
+    assert_eq!(td.to_string(), "abcdefg");
+    assert!(td.stats().major_nodes > stats.major_nodes + 1);
+}
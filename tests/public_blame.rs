@@ -0,0 +1,137 @@
+use simple_text_crdt::{
+    blame::{Attribution, BlameRun},
+    offset::CharOffset,
+    treedoc::Signal,
+};
+
+mod common;
+use common::{delete, insert, site, type_at};
+
+fn by(site: u64, time: std::ops::RangeInclusive<u64>) -> Attribution {
+    Attribution { site, time }
+}
+
+fn run(inserted: Attribution, deleted: Option<Attribution>, text: &str) -> BlameRun {
+    BlameRun {
+        inserted,
+        deleted,
+        text: text.to_string(),
+    }
+}
+
+#[test]
+fn test_blame_authors_and_deletions() {
+    let mut a = site(1);
+    let mut b = site(2);
+    for sig in type_at(&mut a, 0, "hello ") {
+        b.apply(sig).unwrap();
+    }
+    for sig in type_at(&mut b, 6, "world") {
+        a.apply(sig).unwrap();
+    }
+    assert_eq!(
+        a.blame(..),
+        vec![
+            run(by(1, 1..=6), None, "hello "),
+            run(by(2, 7..=11), None, "world")
+        ]
+    );
+
+    for sig in delete(&mut a, 6, 3) {
+        b.apply(sig).unwrap();
+    }
+    assert_eq!(b.to_string(), "hello ld");
+    let deleted = run(by(2, 7..=9), Some(by(1, 12..=14)), "wor");
+    assert_eq!(
+        b.blame(..),
+        vec![
+            run(by(1, 1..=6), None, "hello "),
+            deleted.clone(),
+            run(by(2, 10..=11), None, "ld")
+        ]
+    );
+    // Deleted text goes with the char after it
+    assert_eq!(b.blame(0..6), vec![run(by(1, 1..=6), None, "hello ")]);
+    assert_eq!(
+        b.blame(6..=6),
+        vec![deleted.clone(), run(by(2, 10..=10), None, "l")]
+    );
+    assert_eq!(b.blame(2..4), vec![run(by(1, 3..=4), None, "ll")]);
+    assert_eq!(b.blame(8..), vec![]);
+    assert_eq!(a.blame(..), b.blame(..));
+
+    // At the end it goes with the last char
+    delete(&mut b, 7, 1);
+    assert_eq!(
+        b.blame(6..),
+        vec![
+            deleted,
+            run(by(2, 10..=10), None, "l"),
+            run(by(2, 11..=11), Some(by(2, 15..=15)), "d")
+        ]
+    );
+    assert_eq!(b.blame(7..), vec![]);
+}
+
+#[test]
+fn test_blame_converges() {
+    let mut a = site(1);
+    let mut b = site(2);
+    for sig in type_at(&mut a, 0, "abc") {
+        b.apply(sig).unwrap();
+    }
+    // Both delete the same char, b with the later clock
+    let from_a = delete(&mut a, 1, 1);
+    let mut from_b = type_at(&mut b, 3, "xyz");
+    from_b.extend(delete(&mut b, 1, 1));
+    for sig in from_a {
+        b.apply(sig).unwrap();
+    }
+    for sig in from_b {
+        a.apply(sig).unwrap();
+    }
+    assert_eq!(a.blame(..), b.blame(..));
+    assert_eq!(a.blame(1..2)[0], run(by(1, 2..=2), Some(by(1, 4..=4)), "b"));
+
+    // Snapshots carry the stamps
+    let mut rebuilt = site(3);
+    for sig in b.snapshot() {
+        rebuilt.apply(sig).unwrap();
+    }
+    assert_eq!(rebuilt.blame(..), b.blame(..));
+    // and the clock, so later edits are newer
+    insert(&mut rebuilt, 0, '>');
+    assert_eq!(rebuilt.blame(0..1), vec![run(by(3, 8..=8), None, ">")]);
+}
+
+#[test]
+fn test_blame_follows_moves() {
+    let mut a = site(1);
+    let mut b = site(2);
+    for sig in type_at(&mut a, 0, "one two") {
+        b.apply(sig).unwrap();
+    }
+    let sig = b.move_range(4..7, 0).unwrap();
+    b.apply(sig.clone()).unwrap();
+    a.apply(sig).unwrap();
+    assert_eq!(a.to_string(), "twoone ");
+    assert_eq!(a.blame(..), vec![run(by(1, 1..=7), None, "twoone "),]);
+    assert_eq!(a.blame(0..3), vec![run(by(1, 5..=7), None, "two")]);
+}
+
+#[test]
+fn test_edits_made_before_applying_have_their_own_stamps() {
+    let mut a = site(1);
+    type_at(&mut a, 0, "ab");
+    let del = a.delete(CharOffset(0)).unwrap();
+    let ins = a.insert(2, 'c').unwrap();
+    a.apply(Signal::Delete(del)).unwrap();
+    a.apply(Signal::Insert(ins)).unwrap();
+    assert_eq!(
+        a.blame(..),
+        vec![
+            run(by(1, 1..=1), Some(by(1, 3..=3)), "a"),
+            run(by(1, 2..=4), None, "bc"),
+        ]
+    );
+}
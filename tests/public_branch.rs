@@ -0,0 +1,153 @@
+use std::{cell::RefCell, collections::HashSet, rc::Rc};
+
+use simple_text_crdt::{
+    allocation::Allocation,
+    marks::Mark,
+    node::Node,
+    offset::CharOffset,
+    treedoc::{Signal, Treedoc},
+};
+
+mod common;
+use common::{Rng, delete, edit, site, type_at};
+
+// Every major node of the tree, by address
+fn major_nodes(node: &Option<Rc<RefCell<Node>>>, out: &mut HashSet<*const RefCell<Node>>) {
+    let Some(node) = node else {
+        return;
+    };
+    out.insert(Rc::as_ptr(node));
+    let node = node.borrow();
+    for mini in node.children.borrow().iter() {
+        major_nodes(&mini.borrow().left, out);
+        major_nodes(&mini.borrow().right, out);
+    }
+    major_nodes(&node.left, out);
+    major_nodes(&node.right, out);
+}
+
+#[test]
+fn test_fork_shares_until_edited() {
+    let mut main = Treedoc {
+        allocation: Allocation::Balanced,
+        ..Treedoc::default()
+    };
+    type_at(&mut main, 0, &"abcdefgh".repeat(128));
+    let mut before = HashSet::new();
+    major_nodes(&main.root, &mut before);
+
+    let mut branch = main.fork(2);
+    delete(&mut branch, 500, 1);
+    type_at(&mut branch, 100, "x");
+    assert_eq!(main.to_string(), "abcdefgh".repeat(128));
+    assert_eq!(branch.doc_length, main.doc_length);
+
+    // Only the nodes on the two edited paths were copied
+    let (mut ours, mut theirs) = (HashSet::new(), HashSet::new());
+    major_nodes(&main.root, &mut ours);
+    major_nodes(&branch.root, &mut theirs);
+    assert_eq!(ours, before);
+    assert!(theirs.difference(&ours).count() < 50);
+    assert!(ours.intersection(&theirs).count() > 950);
+
+    // Edits on the original don't show up in the branch either
+    type_at(&mut main, 0, "y");
+    assert!(branch.to_string().starts_with('a'));
+}
+
+#[test]
+fn test_branch_merges_back() {
+    let mut main = Treedoc::default();
+    type_at(&mut main, 0, "hello world");
+    main.checkpoint("suggest");
+    let mut branch = main.fork(2);
+    delete(&mut branch, 6, 5);
+    type_at(&mut branch, 6, "there");
+    // Meanwhile on the original
+    type_at(&mut main, 11, "!");
+    let sig = main.mark(0..5, Mark::Bold).unwrap();
+    edit(&mut main, Signal::Mark(sig));
+
+    let since_fork = branch.changes_since(&branch.checkpoints["suggest"]);
+    assert_eq!(since_fork.len(), 10);
+    assert_eq!(main.to_string(), "hello world!");
+    assert_eq!(branch.to_string(), "hello there");
+
+    main.merge(&branch).unwrap();
+    branch.merge(&main).unwrap();
+    assert_eq!(main.to_string(), "hello there!");
+    assert_eq!(branch.to_string(), main.to_string());
+    assert_eq!(branch.root_hash(), main.root_hash());
+    assert_eq!(branch.formatted_runs(0..12), main.formatted_runs(0..12));
+    assert!(main.changes_since(&branch.version).is_empty());
+    assert_eq!(main.text_at(&main.checkpoints["suggest"]), "hello world");
+
+    // The same edits sent to a replica that never forked
+    let mut other = site(3);
+    for sig in main.changes_since(&other.version) {
+        other.apply(sig).unwrap();
+    }
+    assert_eq!(other.to_string(), "hello there!");
+}
+
+#[test]
+fn test_forks_stay_independent() {
+    let mut rng = Rng(0x2545f4914f6cdd1d);
+    let mut docs = vec![Treedoc::default()];
+    // Replicas seeing the same edits without sharing any nodes
+    let mut plain = vec![Treedoc::default()];
+    for _ in 0..300 {
+        let k = rng.below(docs.len());
+        match rng.below(20) {
+            0 if docs.len() < 5 => {
+                let id = docs.len() as u64 + 1;
+                docs.push(docs[k].fork(id));
+                let mut copy = site(id);
+                for sig in plain[k].snapshot() {
+                    copy.apply(sig).unwrap();
+                }
+                plain.push(copy);
+            }
+            1 => {
+                let j = rng.below(docs.len());
+                let changes = docs[j].changes_since(&docs[k].version);
+                if !changes.is_empty() {
+                    docs[k].apply(Signal::Transaction(changes)).unwrap();
+                }
+                let changes = plain[j].changes_since(&plain[k].version);
+                if !changes.is_empty() {
+                    plain[k].apply(Signal::Transaction(changes)).unwrap();
+                }
+            }
+            _ => {
+                let td = &mut docs[k];
+                let len = td.doc_length;
+                let sig = match rng.below(8) {
+                    0 if len > 1 => td.move_range(0..1, len).unwrap(),
+                    1 | 2 if len > 0 => {
+                        Signal::Delete(td.delete(CharOffset(rng.below(len))).unwrap())
+                    }
+                    _ => {
+                        let ch = (b'a' + rng.below(26) as u8) as char;
+                        Signal::Insert(td.insert(rng.below(len + 1), ch).unwrap())
+                    }
+                };
+                td.apply(sig.clone()).unwrap();
+                plain[k].apply(sig).unwrap();
+            }
+        }
+        for (td, copy) in docs.iter().zip(&plain) {
+            assert_eq!(td.to_string(), copy.to_string());
+            assert_eq!(td.root_hash(), copy.root_hash());
+        }
+    }
+    for i in 1..docs.len() {
+        let (first, rest) = docs.split_at_mut(i);
+        first[0].merge(&rest[0]).unwrap();
+    }
+    for i in 1..docs.len() {
+        let (first, rest) = docs.split_at_mut(i);
+        rest[0].merge(&first[0]).unwrap();
+        assert_eq!(rest[0].to_string(), first[0].to_string());
+    }
+}
@@ -0,0 +1,120 @@
+use std::io::ErrorKind;
+
+use simple_text_crdt::treedoc::Treedoc;
+
+mod common;
+use common::{Rng, delete, insert, site};
+
+#[test]
+fn test_concurrent_inserts_into_one_slot() {
+    let mut a = site(1);
+    let mut b = site(2);
+    let sig = insert(&mut a, 0, 'x');
+    b.apply(sig).unwrap();
+    // Both type right after the 'x' and pick the same slot
+    let from_a = insert(&mut a, 1, 'a');
+    let from_b = insert(&mut b, 1, 'b');
+    a.apply(from_b.clone()).unwrap();
+    b.apply(from_a.clone()).unwrap();
+    assert_eq!(a.to_string(), b.to_string());
+    assert_eq!(a.doc_length, 3);
+    // The same insert twice is rejected
+    assert_eq!(
+        a.apply(from_a).unwrap_err().kind(),
+        ErrorKind::AlreadyExists
+    );
+    assert_eq!(a.to_string(), b.to_string());
+}
+
+#[test]
+fn test_missing_parent_is_not_found() {
+    let mut a = site(1);
+    let mut b = site(2);
+    let first = insert(&mut a, 0, 'a');
+    let second = insert(&mut a, 1, 'b');
+    let gone = delete(&mut a, 0, 1).remove(0);
+    // Delivered out of order, both wait for the first insert
+    assert_eq!(
+        b.apply(second.clone()).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    assert_eq!(
+        b.apply(gone.clone()).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    assert_eq!(b.to_string(), "");
+    for sig in [first, second, gone] {
+        b.apply(sig).unwrap();
+    }
+    assert_eq!(b.to_string(), "b");
+    assert_eq!(a.to_string(), b.to_string());
+}
+
+#[test]
+fn test_deletes_keep_tombstones() {
+    let mut a = site(1);
+    let mut b = site(2);
+    for (i, ch) in "abc".chars().enumerate() {
+        let sig = insert(&mut a, i, ch);
+        b.apply(sig).unwrap();
+    }
+    // a deletes the 'b' twice over, b inserts right after it
+    let from_a = delete(&mut a, 1, 1).remove(0);
+    let from_b = insert(&mut b, 2, 'x');
+    let again = delete(&mut b, 1, 1).remove(0);
+    a.apply(from_b).unwrap();
+    a.apply(again).unwrap();
+    b.apply(from_a).unwrap();
+    assert_eq!(a.to_string(), "axc");
+    assert_eq!(a.to_string(), b.to_string());
+    assert_eq!(a.doc_length, 3);
+}
+
+#[test]
+fn test_random_concurrent_edits_converge() {
+    let mut rng = Rng(0x853c49e6748fea9b);
+    let mut replicas: Vec<Treedoc> = (1..=3).map(site).collect();
+    for _ in 0..100 {
+        // Every replica edits, then gets the others' edits in its own order
+        let mut sigs = Vec::new();
+        for td in replicas.iter_mut() {
+            let len = td.doc_length;
+            let sig = if len > 0 && rng.below(3) == 0 {
+                delete(td, rng.below(len), 1).remove(0)
+            } else {
+                let ch = (b'a' + rng.below(26) as u8) as char;
+                insert(td, rng.below(len + 1), ch)
+            };
+            sigs.push(sig);
+        }
+        for (i, td) in replicas.iter_mut().enumerate() {
+            let mut order: Vec<usize> = (0..sigs.len()).filter(|j| *j != i).collect();
+            if rng.below(2) == 0 {
+                order.reverse();
+            }
+            for j in order {
+                td.apply(sigs[j].clone()).unwrap();
+            }
+        }
+        assert_eq!(replicas[0].to_string(), replicas[1].to_string());
+        assert_eq!(replicas[1].to_string(), replicas[2].to_string());
+    }
+}
+
+#[test]
+fn test_failed_apply_changes_nothing() {
+    let mut a = site(1);
+    let mut b = site(2);
+    let first = insert(&mut a, 0, 'a');
+    b.apply(first).unwrap();
+    for i in 1..50 {
+        insert(&mut a, i, 'x');
+    }
+    // Its parent is one of the inserts b hasn't seen, and its clock is far ahead
+    let late = insert(&mut a, 50, 'y');
+    let (lamport, hash) = (b.lamport, b.root_hash());
+    assert_eq!(b.apply(late).unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(b.lamport, lamport);
+    assert_eq!(b.root_hash(), hash);
+    assert_eq!(b.to_string(), "a");
+}
@@ -0,0 +1,23 @@
+use simple_text_crdt::treedoc::{Signal, Treedoc};
+
+#[test]
+fn test_to_dot() {
+    let mut td = Treedoc::default();
+    for (pos, ch) in [(0, 'b'), (0, 'a'), (1, 'c'), (1, '"')] {
+        let sig = td.insert(pos, ch).unwrap();
+        td.apply(Signal::Insert(sig)).unwrap();
+    }
+    // The deleted 'a' stays in the tree as a tombstone, drawn dashed
+    let sig = td.delete(0).unwrap();
+    td.apply(Signal::Delete(sig)).unwrap();
+
+    let dot = td.to_dot();
+    assert!(dot.starts_with("digraph treedoc {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains("label=\"root\\nlen 3\""));
+    assert_eq!(dot.matches("shape=box").count(), 4);
+    assert_eq!(dot.matches("dashed").count(), 1);
+    assert!(dot.contains("'\\\"' (1)"));
+    assert_eq!(dot.matches("label=\"L\"").count(), 2);
+    assert_eq!(dot.matches("label=\"R\"").count(), 2);
+}
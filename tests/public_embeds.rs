@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+
+use simple_text_crdt::{
+    encoding::{from_bytes, to_bytes},
+    node::{Atom, EMBED_CHAR},
+    offset::Utf16Offset,
+    treedoc::{Signal, Treedoc},
+};
+
+fn image(src: &str) -> Atom {
+    let metadata = BTreeMap::from([("src".to_string(), src.to_string())]);
+    Atom::Embed("img-1".to_string(), metadata)
+}
+
+#[test]
+fn test_embed_takes_one_slot() {
+    let mut td = Treedoc::new('a');
+    let sig = td.insert(1, image("cat.png")).unwrap();
+    td.apply(Signal::Insert(sig)).unwrap();
+    let sig = td.insert(2, 'b').unwrap();
+    td.apply(Signal::Insert(sig)).unwrap();
+
+    assert_eq!(td.doc_length, 3);
+    assert_eq!(td.len_utf16(), 3);
+    assert_eq!(td.to_string(), format!("a{EMBED_CHAR}b"));
+    assert_eq!(td.char_at(1), Some(image("cat.png")));
+
+    let sig = td.insert(Utf16Offset(2), 'x').unwrap();
+    td.apply(Signal::Insert(sig)).unwrap();
+    assert_eq!(td.to_string(), format!("a{EMBED_CHAR}xb"));
+}
+
+#[test]
+fn test_signals_roundtrip_through_bytes() {
+    let mut local = Treedoc::new('a');
+    let mut remote = Treedoc::new('a');
+
+    for atom in [image("dog.png"), Atom::Char('😀')] {
+        let sig = Signal::Insert(local.insert(1, atom).unwrap());
+        let bytes = to_bytes(&sig);
+        local.apply(sig).unwrap();
+        remote.apply(from_bytes(&bytes).unwrap()).unwrap();
+    }
+    let sig = local.delete(0).unwrap();
+    let bytes = to_bytes(&Signal::Delete(sig.clone()));
+    local.apply(Signal::Delete(sig)).unwrap();
+    remote.apply(from_bytes(&bytes).unwrap()).unwrap();
+
+    assert_eq!(
+        remote.atoms().collect::<Vec<_>>(),
+        local.atoms().collect::<Vec<_>>()
+    );
+    assert_eq!(remote.atoms().nth(1), Some(image("dog.png")));
+
+    assert!(from_bytes::<Signal>(&bytes[..bytes.len() - 1]).is_err());
+    assert!(from_bytes::<Signal>(&[9]).is_err());
+}
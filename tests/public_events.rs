@@ -0,0 +1,112 @@
+use std::{cell::RefCell, rc::Rc};
+
+use simple_text_crdt::{
+    events::Change,
+    marks::Mark,
+    offset::CharOffset,
+    treedoc::{Signal, Treedoc},
+};
+
+mod common;
+use common::record;
+
+// Replays changes on a plain string, the way a UI would patch its buffer
+fn patch(view: &mut Vec<char>, changes: &[Change]) {
+    for change in changes {
+        match change {
+            Change::Inserted { index, text } => {
+                for (i, ch) in text.chars().enumerate() {
+                    view.insert(index + i, ch);
+                }
+            }
+            Change::Deleted { index, len } => {
+                view.drain(*index..index + len);
+            }
+            Change::Formatted { .. } => {}
+            Change::Transaction(changes) => patch(view, changes),
+        }
+    }
+}
+
+#[test]
+fn test_local_changes_are_reported() {
+    let mut td = Treedoc::default();
+    let changes = record(&mut td);
+    for (pos, ch) in [(0, 'a'), (1, 'c'), (1, 'b')] {
+        let sig = td.insert(pos, ch).unwrap();
+        td.apply(Signal::Insert(sig)).unwrap();
+    }
+    let sig = td.delete(CharOffset(0)).unwrap();
+    td.apply(Signal::Delete(sig.clone())).unwrap();
+    // Deleting twice changes nothing visible
+    td.apply(Signal::Delete(sig)).unwrap();
+    let sig = td.mark(0..2, Mark::Bold).unwrap();
+    td.apply(Signal::Mark(sig)).unwrap();
+
+    assert_eq!(
+        *changes.borrow(),
+        vec![
+            Change::Inserted {
+                index: 0,
+                text: "a".to_string()
+            },
+            Change::Inserted {
+                index: 1,
+                text: "c".to_string()
+            },
+            Change::Inserted {
+                index: 1,
+                text: "b".to_string()
+            },
+            Change::Deleted { index: 0, len: 1 },
+            Change::Formatted { range: 0..2 },
+        ]
+    );
+}
+
+#[test]
+fn test_remote_changes_patch_a_view() {
+    let mut a = Treedoc::default();
+    let mut b = Treedoc {
+        unique_disambiguator: 2,
+        ..Treedoc::default()
+    };
+    let changes = record(&mut b);
+
+    let mut sigs = Vec::new();
+    for (pos, ch) in [(0, 'h'), (1, 'i'), (0, '>'), (3, '!')] {
+        let sig = a.insert(pos, ch).unwrap();
+        a.apply(Signal::Insert(sig.clone())).unwrap();
+        sigs.push(Signal::Insert(sig));
+    }
+    let sig = a.delete(CharOffset(1)).unwrap();
+    a.apply(Signal::Delete(sig.clone())).unwrap();
+    sigs.push(Signal::Delete(sig));
+    // A concurrent insert on b lands in the middle of a's text
+    let own = b.insert(0, 'x').unwrap();
+    b.apply(Signal::Insert(own.clone())).unwrap();
+    a.apply(Signal::Insert(own)).unwrap();
+
+    for sig in sigs {
+        b.apply(sig).unwrap();
+    }
+    let mut view = Vec::new();
+    patch(&mut view, &changes.borrow());
+    assert_eq!(view.iter().collect::<String>(), b.to_string());
+    assert_eq!(b.to_string(), a.to_string());
+}
+
+#[test]
+fn test_unsubscribe() {
+    let mut td = Treedoc::default();
+    let changes = Rc::new(RefCell::new(Vec::new()));
+    let sink = changes.clone();
+    let id = td.subscribe(move |change| sink.borrow_mut().push(change.clone()));
+    let sig = td.insert(0, 'a').unwrap();
+    td.apply(Signal::Insert(sig)).unwrap();
+    assert!(td.unsubscribe(id));
+    assert!(!td.unsubscribe(id));
+    let sig = td.insert(1, 'b').unwrap();
+    td.apply(Signal::Insert(sig)).unwrap();
+    assert_eq!(changes.borrow().len(), 1);
+}
@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+
+use simple_text_crdt::{
+    encoding::{from_bytes, to_bytes},
+    gossip::{GossipMessage, GossipNode},
+    node::SDIS,
+    offset::CharOffset,
+    treedoc::Signal,
+};
+
+mod common;
+use common::{Rng, text};
+
+// In-process network that loses a share of the messages it carries
+struct Network {
+    queue: VecDeque<(SDIS, SDIS, Vec<u8>)>,
+    loss_percent: usize,
+    rng: Rng,
+}
+
+impl Network {
+    fn send(&mut self, from: SDIS, to: SDIS, msg: &GossipMessage) {
+        if self.rng.below(100) >= self.loss_percent {
+            self.queue.push_back((from, to, to_bytes(msg)));
+        }
+    }
+
+    // Delivers everything queued, including the replies it causes
+    fn run(&mut self, nodes: &mut [GossipNode]) {
+        while let Some((from, to, bytes)) = self.queue.pop_front() {
+            let node = nodes.iter_mut().find(|n| n.site() == to).unwrap();
+            let replies = node.receive(from, from_bytes(&bytes).unwrap()).unwrap();
+            for reply in replies {
+                self.send(to, from, &reply);
+            }
+        }
+    }
+}
+
+fn random_edit(node: &mut GossipNode, rng: &mut Rng) {
+    let len = node.doc.atoms().count();
+    let sig = if len > 0 && rng.below(4) == 0 {
+        Signal::Delete(node.doc.delete(CharOffset(rng.below(len))).unwrap())
+    } else {
+        let ch = (b'a' + rng.below(26) as u8) as char;
+        let pos = rng.below(len + 1);
+        Signal::Insert(node.doc.insert(CharOffset(pos), ch).unwrap())
+    };
+    node.apply_local(sig).unwrap();
+}
+
+fn converged(nodes: &[GossipNode]) -> bool {
+    nodes
+        .iter()
+        .all(|n| n.log.version() == nodes[0].log.version())
+}
+
+#[test]
+fn test_converges_under_message_loss() {
+    let mut net = Network {
+        queue: VecDeque::new(),
+        loss_percent: 30,
+        rng: Rng(0x9E37_79B9_7F4A_7C15),
+    };
+    let mut nodes: Vec<GossipNode> = (1..=5)
+        .map(|site| GossipNode::new(site, site * 7))
+        .collect();
+    for node in nodes.iter_mut() {
+        for site in 1..=5 {
+            node.add_peer(site);
+        }
+    }
+
+    let mut rounds = 0;
+    while rounds < 40 || !converged(&nodes) {
+        rounds += 1;
+        assert!(rounds < 1000, "replicas did not converge");
+        for node in nodes.iter_mut() {
+            // Everybody edits concurrently for a while, then only gossips
+            if rounds < 40 {
+                random_edit(node, &mut net.rng);
+            }
+            if let Some((to, msg)) = node.tick() {
+                net.send(node.site(), to, &msg);
+            }
+        }
+        net.run(&mut nodes);
+    }
+
+    let merged = text(&nodes[0].doc);
+    assert!(!merged.is_empty());
+    for node in &nodes {
+        assert_eq!(text(&node.doc), merged);
+        assert_eq!(node.log.len(), 5 * 39);
+    }
+}
+
+#[test]
+fn test_ops_spread_past_direct_peers() {
+    let mut net = Network {
+        queue: VecDeque::new(),
+        loss_percent: 0,
+        rng: Rng(1),
+    };
+    // A line 1 - 2 - 3 - 4 where each node only knows its neighbours
+    let mut nodes: Vec<GossipNode> = (1..=4).map(|site| GossipNode::new(site, site)).collect();
+    for i in 0..3 {
+        nodes[i].add_peer(i as SDIS + 2);
+        nodes[i + 1].add_peer(i as SDIS + 1);
+    }
+    for (i, ch) in "end".chars().enumerate() {
+        let sig = nodes[3].doc.insert(CharOffset(i), ch).unwrap();
+        nodes[3].apply_local(Signal::Insert(sig)).unwrap();
+    }
+    let sig = nodes[0].doc.insert(CharOffset(0), '>').unwrap();
+    nodes[0].apply_local(Signal::Insert(sig)).unwrap();
+
+    let mut rounds = 0;
+    while !converged(&nodes) {
+        rounds += 1;
+        assert!(rounds < 100, "replicas did not converge");
+        for node in nodes.iter_mut() {
+            if let Some((to, msg)) = node.tick() {
+                net.send(node.site(), to, &msg);
+            }
+        }
+        net.run(&mut nodes);
+    }
+    assert_eq!(nodes[0].peers(), &[2]);
+    for node in &nodes {
+        assert_eq!(text(&node.doc), text(&nodes[0].doc));
+        assert_eq!(text(&node.doc).len(), 4);
+    }
+}
@@ -0,0 +1,164 @@
+use simple_text_crdt::{
+    history::{ClockVector, DiffRun},
+    offset::CharOffset,
+    treedoc::{Signal, Treedoc},
+};
+
+mod common;
+use common::{Rng, delete, edit, site, type_at};
+
+#[test]
+fn test_text_at_past_versions() {
+    let mut a = site(1);
+    let mut ops = type_at(&mut a, 0, "hello");
+    let v1 = a.version.clone();
+    ops.extend(type_at(&mut a, 5, " world"));
+    let v2 = a.version.clone();
+    ops.extend(delete(&mut a, 0, 6));
+    let v3 = a.version.clone();
+
+    assert_eq!(a.text_at(&ClockVector::new()), "");
+    // Lamport clocks, one per edit of the site
+    assert_eq!(v1.get(1), 5);
+    assert!(v2.includes((11, 1)) && !v1.includes((6, 1)));
+    assert_eq!(a.text_at(&v1), "hello");
+    assert_eq!(a.text_at(&v2), "hello world");
+    assert_eq!(a.text_at(&v3), "world");
+    assert_eq!(
+        a.diff_versions(&v1, &v3),
+        vec![
+            DiffRun::Deleted("hello".to_string()),
+            DiffRun::Inserted("world".to_string())
+        ]
+    );
+    assert_eq!(
+        a.diff_versions(&v2, &v3),
+        vec![
+            DiffRun::Deleted("hello ".to_string()),
+            DiffRun::Kept("world".to_string())
+        ]
+    );
+    assert_eq!(
+        a.diff_versions(&v3, &v3),
+        vec![DiffRun::Kept("world".to_string())]
+    );
+
+    // Versions mean the same on every replica, and snapshots keep the history
+    let mut b = site(2);
+    for sig in ops {
+        b.apply(sig).unwrap();
+    }
+    assert_eq!(b.version, v3);
+    assert_eq!(b.text_at(&v1), "hello");
+    let mut rebuilt = site(3);
+    for sig in a.snapshot() {
+        rebuilt.apply(sig).unwrap();
+    }
+    assert_eq!(rebuilt.text_at(&v2), "hello world");
+}
+
+#[test]
+fn test_concurrent_history() {
+    let mut a = site(1);
+    let mut b = site(2);
+    for sig in type_at(&mut a, 0, "abc") {
+        b.apply(sig).unwrap();
+    }
+    let base = a.version.clone();
+    // Both delete the 'b', b also types
+    let from_a = delete(&mut a, 1, 1);
+    let mut from_b = delete(&mut b, 1, 1);
+    from_b.extend(type_at(&mut b, 2, "d"));
+    let only_b = b.version.clone();
+    for sig in from_a {
+        b.apply(sig).unwrap();
+    }
+    for sig in from_b {
+        a.apply(sig).unwrap();
+    }
+    assert_eq!(a.version, b.version);
+    assert_eq!(a.text_at(&base), "abc");
+    assert_eq!(a.text_at(&only_b), "acd");
+    assert_eq!(b.text_at(&only_b), "acd");
+    assert_eq!(a.text_at(&a.version), "acd");
+
+    // A move shows the atom at its old place before it
+    let sig = a.move_range(0..1, 3).unwrap();
+    let before = a.version.clone();
+    edit(&mut a, sig);
+    assert_eq!(a.to_string(), "cda");
+    assert_eq!(a.text_at(&before), "acd");
+    assert_eq!(
+        a.diff_versions(&before, &a.version),
+        vec![
+            DiffRun::Deleted("a".to_string()),
+            DiffRun::Kept("cd".to_string()),
+            DiffRun::Inserted("a".to_string())
+        ]
+    );
+}
+
+#[test]
+fn test_every_version_replays() {
+    let mut rng = Rng(0x9e3779b97f4a7c15);
+    let mut replicas: Vec<Treedoc> = (1..=3).map(site).collect();
+    // Every state each replica went through
+    let mut seen: Vec<(ClockVector, String)> = Vec::new();
+    for _ in 0..60 {
+        let mut sigs = Vec::new();
+        for td in replicas.iter_mut() {
+            let len = td.doc_length;
+            let sig = match rng.below(6) {
+                0 if len > 1 => {
+                    let from = rng.below(len - 1);
+                    let to = if rng.below(2) == 0 { 0 } else { len };
+                    td.move_range(from..from + 2, to).unwrap()
+                }
+                1 | 2 if len > 0 => Signal::Delete(td.delete(CharOffset(rng.below(len))).unwrap()),
+                _ => {
+                    let ch = (b'a' + rng.below(26) as u8) as char;
+                    Signal::Insert(td.insert(rng.below(len + 1), ch).unwrap())
+                }
+            };
+            sigs.push(edit(td, sig));
+            seen.push((td.version.clone(), td.to_string()));
+        }
+        for (i, td) in replicas.iter_mut().enumerate() {
+            for (j, sig) in sigs.iter().enumerate() {
+                if i != j {
+                    td.apply(sig.clone()).unwrap();
+                    seen.push((td.version.clone(), td.to_string()));
+                }
+            }
+        }
+    }
+    for td in &replicas {
+        for (version, text) in &seen {
+            assert_eq!(td.text_at(version), *text);
+        }
+    }
+}
+
+#[test]
+fn test_hash_covers_edit_stamps() {
+    let mut a = site(1);
+    let mut b = site(2);
+    for sig in type_at(&mut a, 0, "abc") {
+        b.apply(sig).unwrap();
+    }
+    // Both delete the 'b': the same text and tombstones, but not the same history
+    let from_a = delete(&mut a, 1, 1);
+    let from_b = delete(&mut b, 1, 1);
+    assert_eq!(a.to_string(), b.to_string());
+    assert_ne!(a.root_hash(), b.root_hash());
+    assert_eq!(a.diff(&b).len(), 1);
+
+    for sig in from_b {
+        a.apply(sig).unwrap();
+    }
+    for sig in from_a {
+        b.apply(sig).unwrap();
+    }
+    assert_eq!(a.root_hash(), b.root_hash());
+    assert!(a.diff(&b).is_empty());
+}
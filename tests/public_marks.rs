@@ -0,0 +1,107 @@
+use simple_text_crdt::{
+    marks::{FormattedRun, Mark, MarkType},
+    treedoc::{Signal, Treedoc},
+};
+
+fn build(text: &str, site: u64) -> Treedoc {
+    let mut chars = text.chars();
+    let mut td = Treedoc::new(chars.next().unwrap());
+    for ch in chars {
+        let sig = td.insert(td.doc_length, ch).unwrap();
+        td.apply(Signal::Insert(sig)).unwrap();
+    }
+    td.unique_disambiguator = site;
+    td
+}
+
+fn run(text: &str, marks: &[Mark]) -> FormattedRun {
+    FormattedRun {
+        text: text.to_string(),
+        marks: marks.to_vec(),
+    }
+}
+
+#[test]
+fn test_bold_expands_link_does_not() {
+    let mut td = build("abcd", 1);
+
+    let sig = td.mark(1..4, Mark::Bold).unwrap();
+    td.apply(Signal::Mark(sig)).unwrap();
+    let sig = td.mark(0..2, Mark::Link("x.org".into())).unwrap();
+    td.apply(Signal::Mark(sig)).unwrap();
+
+    let sig = td.insert(td.doc_length, 'e').unwrap();
+    td.apply(Signal::Insert(sig)).unwrap();
+    let sig = td.insert(2, 'z').unwrap();
+    td.apply(Signal::Insert(sig)).unwrap();
+
+    let link = || Mark::Link("x.org".into());
+    assert_eq!(
+        td.formatted_runs(0..td.doc_length),
+        vec![
+            run("a", &[link()]),
+            run("b", &[Mark::Bold, link()]),
+            run("zcde", &[Mark::Bold]),
+        ]
+    );
+    assert_eq!(td.formatted_runs(3..4), vec![run("c", &[Mark::Bold])]);
+
+    // Marks don't grow at their start, not even at the start of the document
+    let sig = td.insert(0, 'y').unwrap();
+    td.apply(Signal::Insert(sig)).unwrap();
+    assert_eq!(
+        td.formatted_runs(0..2),
+        vec![run("y", &[]), run("a", &[link()])]
+    );
+}
+
+#[test]
+fn test_concurrent_marks_and_edits_converge() {
+    let mut a = build("abcdef", 1);
+    let mut b = build("abcdef", 2);
+
+    // a: bold "bcd", then unbold "d"
+    let a_bold = a.mark(1..4, Mark::Bold).unwrap();
+    a.apply(Signal::Mark(a_bold.clone())).unwrap();
+    let a_unbold = a.unmark(3..4, MarkType::Bold).unwrap();
+    a.apply(Signal::Mark(a_unbold.clone())).unwrap();
+
+    // b concurrently: italic "cde" and a new char between 'b' and 'c'
+    let b_italic = b.mark(2..5, Mark::Italic).unwrap();
+    b.apply(Signal::Mark(b_italic.clone())).unwrap();
+    let b_insert = b.insert(2, 'x').unwrap();
+    b.apply(Signal::Insert(b_insert.clone())).unwrap();
+
+    for sig in [Signal::Insert(b_insert), Signal::Mark(b_italic)] {
+        a.apply(sig).unwrap();
+    }
+    for sig in [Signal::Mark(a_unbold), Signal::Mark(a_bold)] {
+        b.apply(sig).unwrap();
+    }
+
+    let expected = vec![
+        run("a", &[]),
+        run("bx", &[Mark::Bold]),
+        run("c", &[Mark::Bold, Mark::Italic]),
+        run("de", &[Mark::Italic]),
+        run("f", &[]),
+    ];
+    assert_eq!(a.formatted_runs(0..a.doc_length), expected);
+    assert_eq!(b.formatted_runs(0..b.doc_length), expected);
+}
+
+#[test]
+fn test_mark_survives_deleted_anchor() {
+    let mut td = build("abcd", 1);
+    let sig = td.mark(1..3, Mark::Italic).unwrap();
+    td.apply(Signal::Mark(sig)).unwrap();
+
+    let sig = td.delete(1).unwrap();
+    td.apply(Signal::Delete(sig)).unwrap();
+
+    assert_eq!(
+        td.formatted_runs(0..td.doc_length),
+        vec![run("a", &[]), run("c", &[Mark::Italic]), run("d", &[])]
+    );
+    assert!(td.mark(2..2, Mark::Bold).is_err());
+}
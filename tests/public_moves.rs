@@ -0,0 +1,146 @@
+use std::{cell::RefCell, rc::Rc};
+
+use simple_text_crdt::{
+    encoding::{from_bytes, to_bytes},
+    events::Change,
+    offset::CharOffset,
+    treedoc::{Signal, Treedoc},
+};
+
+mod common;
+use common::{site, type_at};
+
+fn replica(id: u64, text: &str) -> (Treedoc, Vec<Signal>) {
+    let mut td = site(id);
+    let sigs = type_at(&mut td, 0, text);
+    (td, sigs)
+}
+
+// Two replicas sharing `text`
+fn pair(text: &str) -> (Treedoc, Treedoc) {
+    let (a, sigs) = replica(1, text);
+    let (mut b, _) = replica(2, "");
+    for sig in sigs {
+        b.apply(sig).unwrap();
+    }
+    (a, b)
+}
+
+fn apply(td: &mut Treedoc, sig: &Signal) {
+    td.apply(from_bytes(&to_bytes(sig)).unwrap()).unwrap();
+}
+
+#[test]
+fn test_move_range() {
+    let (mut td, _) = replica(1, "abcdef");
+    let sig = td.move_range(1..3, 5).unwrap();
+    td.apply(sig).unwrap();
+    assert_eq!(td.to_string(), "adebcf");
+
+    let sig = td.move_range(3..5, 0).unwrap();
+    td.apply(sig).unwrap();
+    assert_eq!(td.to_string(), "bcadef");
+    assert_eq!(td.doc_length, 6);
+
+    assert!(td.move_range(1..4, 2).is_err());
+}
+
+#[test]
+fn test_concurrent_moves_do_not_duplicate() {
+    let (mut a, mut b) = pair("hello world");
+    // Both move "world" to the front, to different spots
+    let move_a = a.move_range(6..11, 0).unwrap();
+    let move_b = b.move_range(6..11, 5).unwrap();
+    a.apply(move_a.clone()).unwrap();
+    b.apply(move_b.clone()).unwrap();
+    apply(&mut a, &move_b);
+    apply(&mut b, &move_a);
+
+    assert_eq!(a.to_string(), b.to_string());
+    assert_eq!(a.root_hash(), b.root_hash());
+    // Equal clocks, so the higher site wins
+    assert_eq!(a.to_string(), "helloworld ");
+}
+
+#[test]
+fn test_move_keeps_identity() {
+    let (mut a, mut b) = pair("abc");
+    let id = a.iter().next().unwrap();
+
+    let mv = a.move_atom(0, 3).unwrap();
+    a.apply(Signal::Move(mv.clone())).unwrap();
+    assert_eq!(a.to_string(), "bca");
+    assert_eq!(a.pos_of(&id), Some(2));
+
+    // b deletes the atom and types next to where it was, not knowing it moved
+    let del = b.delete(CharOffset(0)).unwrap();
+    let ins = b.insert(0, 'x').unwrap();
+    b.apply(Signal::Delete(del.clone())).unwrap();
+    b.apply(Signal::Insert(ins.clone())).unwrap();
+
+    apply(&mut a, &Signal::Delete(del));
+    apply(&mut a, &Signal::Insert(ins));
+    apply(&mut b, &Signal::Move(mv));
+    assert_eq!(a.to_string(), "xbc");
+    assert_eq!(b.to_string(), "xbc");
+    assert_eq!(a.pos_of(&id), None);
+    assert_eq!(a.root_hash(), b.root_hash());
+
+    let mut rebuilt = Treedoc::default();
+    for sig in a.snapshot() {
+        rebuilt.apply(sig).unwrap();
+    }
+    assert_eq!(rebuilt.to_string(), "xbc");
+    assert_eq!(rebuilt.root_hash(), a.root_hash());
+}
+
+#[test]
+fn test_move_changes_patch_a_view() {
+    let (mut a, mut b) = pair("abcdef");
+    let changes = Rc::new(RefCell::new(Vec::new()));
+    let sink = changes.clone();
+    b.subscribe(move |change| sink.borrow_mut().push(change.clone()));
+
+    let sig = a.move_range(0..2, 6).unwrap();
+    a.apply(sig.clone()).unwrap();
+    apply(&mut b, &sig);
+    assert_eq!(b.to_string(), "cdefab");
+
+    let mut view: Vec<char> = "abcdef".chars().collect();
+    let Change::Transaction(inner) = &changes.borrow()[0] else {
+        panic!("expected one transaction change");
+    };
+    for change in inner {
+        match change {
+            Change::Deleted { index, len } => {
+                view.drain(*index..index + len);
+            }
+            Change::Inserted { index, text } => view.insert(*index, text.chars().next().unwrap()),
+            _ => {}
+        }
+    }
+    assert_eq!(view.iter().collect::<String>(), "cdefab");
+}
+
+#[test]
+fn test_moved_atom_moves_again_and_failed_moves_are_undone() {
+    let (mut a, mut b) = pair("abcd");
+    let first = Signal::Move(a.move_atom(0, 4).unwrap());
+    a.apply(first.clone()).unwrap();
+    // The 'a' is now shown at its slot, moving it again still moves the atom
+    let second = Signal::Move(a.move_atom(3, 1).unwrap());
+    a.apply(second.clone()).unwrap();
+    assert_eq!(a.to_string(), "bacd");
+
+    // A transaction that fails half way takes its move back
+    let (mut other, _) = replica(3, "z");
+    let missing = Signal::Delete(other.delete(CharOffset(0)).unwrap());
+    let bad = Signal::Transaction(vec![first.clone(), missing]);
+    assert!(b.apply(bad).is_err());
+    assert_eq!(b.to_string(), "abcd");
+
+    apply(&mut b, &first);
+    apply(&mut b, &second);
+    assert_eq!(b.to_string(), "bacd");
+    assert_eq!(a.root_hash(), b.root_hash());
+}
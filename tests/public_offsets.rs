@@ -0,0 +1,68 @@
+use simple_text_crdt::{
+    offset::{CharOffset, Utf8Offset, Utf16Offset},
+    treedoc::{Signal, Treedoc},
+};
+
+fn build(text: &str) -> Treedoc {
+    let mut chars = text.chars();
+    let mut td = Treedoc::new(chars.next().unwrap());
+    for ch in chars {
+        let sig = td.insert(td.doc_length, ch).unwrap();
+        td.apply(Signal::Insert(sig)).unwrap();
+    }
+    td
+}
+
+fn collect(td: &Treedoc) -> String {
+    let mut nodes = Vec::new();
+    Treedoc::traverse_in_and_collect(&td.root, &mut nodes);
+    nodes.iter().collect()
+}
+
+#[test]
+fn test_lengths() {
+    let td = build("a😀é");
+    assert_eq!(td.doc_length, 3);
+    assert_eq!(td.len_utf8(), 1 + 4 + 2);
+    assert_eq!(td.len_utf16(), 1 + 2 + 1);
+}
+
+#[test]
+fn test_insert_at_utf16_offset() {
+    let mut td = build("a😀b");
+
+    let sig = td.insert(Utf16Offset(3), 'x').unwrap();
+    td.apply(Signal::Insert(sig)).unwrap();
+    assert_eq!(collect(&td), "a😀xb");
+
+    // Between the two halves of the surrogate pair
+    assert!(td.insert(Utf16Offset(2), 'y').is_err());
+}
+
+#[test]
+fn test_delete_at_utf8_offset() {
+    let mut td = build("é😀b");
+
+    let sig = td.delete(Utf8Offset(2)).unwrap();
+    td.apply(Signal::Delete(sig)).unwrap();
+    assert_eq!(collect(&td), "éb");
+    assert_eq!(td.len_utf8(), 3);
+
+    assert!(td.delete(Utf8Offset(1)).is_err());
+    assert!(td.delete(Utf8Offset(3)).is_err());
+
+    // A plain usize is a char index, like CharOffset
+    let sig = td.delete(1).unwrap();
+    td.apply(Signal::Delete(sig)).unwrap();
+    assert_eq!(collect(&td), "é");
+    assert!(td.delete(1).is_err());
+}
+
+#[test]
+fn test_offset_conversions() {
+    let td = build("a😀é");
+    assert_eq!(td.to_utf16_offset(CharOffset(2)).unwrap(), Utf16Offset(3));
+    assert_eq!(td.to_utf8_offset(Utf16Offset(3)).unwrap(), Utf8Offset(5));
+    assert_eq!(td.to_char_offset(Utf8Offset(7)).unwrap(), CharOffset(3));
+    assert!(td.to_char_offset(Utf8Offset(8)).is_err());
+}
@@ -0,0 +1,245 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet},
+};
+
+use simple_text_crdt::{
+    allocation::Allocation,
+    encoding::{from_bytes, to_bytes},
+    pos_id::{CompactPosID, PathComponent, PosID, PosIDInterner},
+    treedoc::{Signal, Treedoc},
+};
+
+mod common;
+use common::{Rng, delete, insert, site};
+
+// Three replicas editing concurrently, merged into the first one
+fn random_doc(rng: &mut Rng, rounds: usize) -> Treedoc {
+    let mut replicas: Vec<Treedoc> = (1..=3).map(site).collect();
+    for _ in 0..rounds {
+        let mut sigs = Vec::new();
+        for td in replicas.iter_mut() {
+            let len = td.doc_length;
+            let sig = if len > 0 && rng.below(4) == 0 {
+                delete(td, rng.below(len), 1).remove(0)
+            } else {
+                insert(td, rng.below(len + 1), 'x')
+            };
+            sigs.push(sig);
+        }
+        for (i, td) in replicas.iter_mut().enumerate() {
+            for (j, sig) in sigs.iter().enumerate() {
+                if i != j {
+                    td.apply(sig.clone()).unwrap();
+                }
+            }
+        }
+    }
+    replicas.swap_remove(0)
+}
+
+// Paths over the components the tree uses, plus the end sentinel
+fn random_pos_id(rng: &mut Rng) -> PosID {
+    let len = rng.below(5);
+    let comps = (0..len).map(|_| match rng.below(5) {
+        0 => PathComponent(0, None),
+        1 => PathComponent(1, None),
+        2 => PathComponent(usize::MAX, None),
+        3 => PathComponent(0, Some(u64::MAX - rng.below(2) as u64)),
+        _ => PathComponent(0, Some(1 + rng.below(3) as u64)),
+    });
+    PosID(comps.collect())
+}
+
+#[test]
+fn test_order_matches_iter() {
+    for seed in 1..30u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x9e3779b97f4a7c15));
+        let td = random_doc(&mut rng, 40);
+        let ids: Vec<PosID> = td.iter().collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]), "seed {seed}");
+
+        let mut shuffled = ids.clone();
+        for i in (1..shuffled.len()).rev() {
+            shuffled.swap(i, rng.below(i + 1));
+        }
+        shuffled.sort();
+        assert_eq!(shuffled, ids);
+        assert!(ids.iter().all(|id| *id < PosID::new_empty_end()));
+    }
+}
+
+#[test]
+fn test_strict_total_order() {
+    let mut rng = Rng(0x2545f4914f6cdd1d);
+    let ids: Vec<PosID> = (0..60).map(|_| random_pos_id(&mut rng)).collect();
+    for a in &ids {
+        assert_eq!(a.cmp(a), Ordering::Equal);
+        for b in &ids {
+            let ab = a.cmp(b);
+            assert_eq!(ab, b.cmp(a).reverse(), "{a} {b}");
+            assert_eq!(ab == Ordering::Equal, a == b, "{a} {b}");
+            assert_eq!(a.partial_cmp(b), Some(ab));
+            for c in &ids {
+                if ab.is_lt() && b < c {
+                    assert!(a < c, "{a} {b} {c}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_subtree_order() {
+    let node = PosID(vec![PathComponent(1, None), PathComponent(0, Some(2))]);
+    let below = |comp| PosID([node.0.clone(), vec![comp]].concat());
+    // Left subtree, the atom itself, right subtree
+    assert!(below(PathComponent(0, None)) < node);
+    assert!(node < below(PathComponent(1, None)));
+    // Minis of a node in disambiguator order, between its subtrees
+    let major = PosID(vec![PathComponent(1, None)]);
+    let mini = |dis| PosID(vec![PathComponent(1, None), PathComponent(0, Some(dis))]);
+    let left = PosID(vec![PathComponent(1, None), PathComponent(0, None)]);
+    let right = PosID(vec![PathComponent(1, None), PathComponent(1, None)]);
+    assert!(left < mini(1) && mini(1) < mini(2) && mini(2) < right);
+    assert!(left < major && major < mini(1));
+}
+
+#[test]
+fn test_pos_ids_as_map_keys() {
+    let mut rng = Rng(7);
+    let td = random_doc(&mut rng, 30);
+    let ids: Vec<PosID> = td.iter().collect();
+
+    // Comments keyed by the char they are attached to come out in document order
+    let mut comments = BTreeMap::new();
+    for (i, id) in ids.iter().enumerate().rev().step_by(3) {
+        comments.insert(id.clone(), format!("comment on {i}"));
+    }
+    let order: Vec<usize> = comments.keys().map(|id| td.pos_of(id).unwrap()).collect();
+    assert!(order.windows(2).all(|w| w[0] < w[1]));
+
+    let set: HashSet<PosID> = ids.iter().chain(&ids).cloned().collect();
+    assert_eq!(set.len(), ids.len());
+
+    let mut interner = PosIDInterner::new();
+    let handles: Vec<_> = ids.iter().map(|id| interner.intern(id)).collect();
+    assert_eq!(interner.intern(&ids[0]), handles[0]);
+    assert_eq!(interner.len(), ids.len());
+    assert_eq!(interner.get(&ids[1]), Some(handles[1]));
+    assert_eq!(interner.resolve(handles[2]).as_ref(), Some(&ids[2]));
+    assert_eq!(
+        interner.cmp(handles[3], handles[1]),
+        Some(Ordering::Greater)
+    );
+    assert_eq!(PosIDInterner::new().resolve(handles[0]), None);
+}
+
+#[test]
+fn test_compact_round_trip() {
+    let mut rng = Rng(0x853c49e6748fea9b);
+    let ids: Vec<PosID> = (0..200).map(|_| random_pos_id(&mut rng)).collect();
+    let compact: Vec<CompactPosID> = ids.iter().map(CompactPosID::from).collect();
+    for (id, c) in ids.iter().zip(&compact) {
+        assert_eq!(c.to_pos_id(), *id);
+        assert_eq!(CompactPosID::from_bytes(c.as_bytes()).unwrap(), *c);
+        assert_eq!(from_bytes::<PosID>(&to_bytes(id)).unwrap(), *id);
+    }
+    for (a, ca) in ids.iter().zip(&compact) {
+        for (b, cb) in ids.iter().zip(&compact) {
+            assert_eq!(ca.cmp(cb), a.cmp(b), "{a} {b}");
+            assert_eq!(ca == cb, a == b);
+        }
+    }
+}
+
+#[test]
+fn test_compact_is_small() {
+    // Typing at the end makes a path one step longer per char
+    let mut td = Treedoc::default();
+    for i in 0..1000 {
+        let sig = td.insert(i, 'x').unwrap();
+        td.apply(Signal::Insert(sig)).unwrap();
+    }
+    let last = td.iter().last().unwrap();
+    assert!(last.0.len() > 500);
+    let compact = CompactPosID::from(&last);
+    assert!(
+        compact.as_bytes().len() < last.0.len() / 7,
+        "{}",
+        compact.as_bytes().len()
+    );
+}
+
+#[test]
+fn test_compact_rejects_bad_bytes() {
+    let id = PosID(vec![
+        PathComponent(1, None),
+        PathComponent(0, None),
+        PathComponent(0, Some(2)),
+    ]);
+    let bytes = CompactPosID::from(&id).as_bytes().to_vec();
+    assert_eq!(bytes, vec![2 << 2, 0b01, 2 << 2 | 1]);
+    for bad in [
+        // Run cut short
+        vec![2 << 2],
+        // Empty run
+        vec![0],
+        // Two runs in a row, or a stray bit past the run
+        vec![1 << 2, 1, 1 << 2, 0, 2 << 2 | 1],
+        vec![2 << 2, 0b101, 2 << 2 | 1],
+        // Long forms where the short ones fit
+        vec![2 << 2, 0b01, 3, 0, 2],
+        vec![2, 1],
+    ] {
+        assert!(CompactPosID::from_bytes(&bad).is_err(), "{bad:?}");
+    }
+}
+
+// Whatever gap we insert into, the new atom's PosID sorts strictly between the
+// visible atoms around it
+#[test]
+fn test_new_pos_id_is_between_neighbours() {
+    new_pos_id_is_between_neighbours(Allocation::Simple);
+    new_pos_id_is_between_neighbours(Allocation::Balanced);
+}
+
+fn new_pos_id_is_between_neighbours(allocation: Allocation) {
+    let mut rng = Rng(0x853c49e6748fea9b);
+    let mut a = Treedoc {
+        allocation,
+        ..site(1)
+    };
+    let mut b = Treedoc {
+        allocation,
+        ..site(2)
+    };
+    for round in 0..200 {
+        // Concurrent edits, so nodes end up with several minis
+        let mut sigs = Vec::new();
+        for td in [&mut a, &mut b] {
+            let len = td.doc_length;
+            let sig = if len > 0 && rng.below(4) == 0 {
+                delete(td, rng.below(len), 1).remove(0)
+            } else {
+                insert(td, rng.below(len + 1), 'x')
+            };
+            sigs.push(sig);
+        }
+        a.apply(sigs[1].clone()).unwrap();
+        b.apply(sigs[0].clone()).unwrap();
+        if round % 10 != 0 {
+            continue;
+        }
+
+        let ids: Vec<PosID> = a.iter().collect();
+        for gap in 0..=ids.len() {
+            // Applied on a fork to read the PosID the atom ends up with
+            let mut probe = a.fork(3);
+            insert(&mut probe, gap, 'y');
+            let new = probe.iter().nth(gap).unwrap();
+            assert!(gap == 0 || ids[gap - 1] < new, "{} {new}", ids[gap - 1]);
+            assert!(gap == ids.len() || new < ids[gap], "{new} {}", ids[gap]);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use simple_text_crdt::{
+    marks::Mark,
+    offset::CharOffset,
+    relay::{RelayClient, RelayServer},
+    treedoc::Signal,
+};
+
+mod common;
+use common::{TIMEOUT, settle, text, wait_joined};
+
+// Server and clients share the test thread, so both sides get polled until the join completes
+fn join(server: &mut RelayServer, room: &str) -> RelayClient {
+    let mut client = RelayClient::connect(server.local_addr(), room).unwrap();
+    wait_joined(server, &mut client);
+    client
+}
+
+fn type_text(client: &mut RelayClient, pos: usize, text: &str) {
+    for (i, ch) in text.chars().enumerate() {
+        let sig = client.doc.insert(pos + i, ch).unwrap();
+        client.apply_local(Signal::Insert(sig)).unwrap();
+    }
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("relay-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn test_rooms_are_separate() {
+    let mut server = RelayServer::bind("127.0.0.1:0", None).unwrap();
+    let mut a = join(&mut server, "notes");
+    let mut b = join(&mut server, "notes");
+    let mut c = join(&mut server, "todo");
+    assert_ne!(a.site(), b.site());
+
+    type_text(&mut a, 0, "abc");
+    type_text(&mut b, 0, "xyz");
+    type_text(&mut c, 0, "other");
+    settle(&mut server, "notes", &mut [&mut a, &mut b]);
+    settle(&mut server, "todo", &mut [&mut c]);
+
+    let merged = text(&a.doc);
+    assert_eq!(merged.len(), 6);
+    assert_eq!(text(&b.doc), merged);
+    assert_eq!(server.doc("notes").unwrap().to_string(), merged);
+    assert_eq!(text(&c.doc), "other");
+}
+
+#[test]
+fn test_late_joiner_gets_snapshot_and_recent_ops() {
+    let mut server = RelayServer::bind("127.0.0.1:0", None).unwrap();
+    server.max_recent = 4;
+    let mut a = join(&mut server, "doc");
+
+    type_text(&mut a, 0, "hello world");
+    let sig = a.doc.delete(CharOffset(5)).unwrap();
+    a.apply_local(Signal::Delete(sig)).unwrap();
+    let sig = a.doc.mark(0..5, Mark::Bold).unwrap();
+    a.apply_local(Signal::Mark(sig)).unwrap();
+    settle(&mut server, "doc", &mut [&mut a]);
+
+    let mut b = join(&mut server, "doc");
+    assert_eq!(b.seq(), server.seq("doc").unwrap());
+    assert_eq!(text(&b.doc), "helloworld");
+    assert_eq!(b.doc.formatted_runs(0..10), a.doc.formatted_runs(0..10));
+
+    type_text(&mut b, 10, "!");
+    settle(&mut server, "doc", &mut [&mut a, &mut b]);
+    assert_eq!(text(&a.doc), "helloworld!");
+}
+
+#[test]
+fn test_rooms_survive_restart() {
+    let dir = temp_dir("restart");
+    let first_site = {
+        let mut server = RelayServer::bind("127.0.0.1:0", Some(dir.clone())).unwrap();
+        server.max_recent = 3;
+        let mut a = join(&mut server, "doc");
+        type_text(&mut a, 0, "persist");
+        settle(&mut server, "doc", &mut [&mut a]);
+        a.site()
+    };
+
+    let mut server = RelayServer::bind("127.0.0.1:0", Some(dir.clone())).unwrap();
+    let b = join(&mut server, "doc");
+    assert_eq!(text(&b.doc), "persist");
+    assert_eq!(b.seq(), 7);
+    // Site ids handed out before the restart are not reused
+    assert!(b.site() > first_site);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_invalid_room_is_rejected() {
+    let mut server = RelayServer::bind("127.0.0.1:0", None).unwrap();
+    let mut client = RelayClient::connect(server.local_addr(), "../etc").unwrap();
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        server.poll_timeout(Duration::from_millis(5)).unwrap();
+        if client.poll().is_err() {
+            break;
+        }
+        assert!(Instant::now() < deadline, "join was not refused");
+    }
+    assert!(!client.is_joined());
+}
+
+#[test]
+fn test_unreadable_room_only_rejects_its_client() {
+    let dir = temp_dir("unreadable");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("broken.snap"), b"not a snapshot").unwrap();
+    let mut server = RelayServer::bind("127.0.0.1:0", Some(dir.clone())).unwrap();
+
+    let mut client = RelayClient::connect(server.local_addr(), "broken").unwrap();
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        server.poll_timeout(Duration::from_millis(5)).unwrap();
+        if client.poll().is_err() {
+            break;
+        }
+        assert!(Instant::now() < deadline, "join was not refused");
+    }
+    assert!(!client.is_joined());
+    assert!(server.doc("broken").is_none());
+
+    // The server keeps serving the other rooms
+    let mut a = join(&mut server, "fine");
+    type_text(&mut a, 0, "ok");
+    settle(&mut server, "fine", &mut [&mut a]);
+    assert_eq!(server.doc("fine").unwrap().to_string(), "ok");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_client_switches_rooms() {
+    let mut server = RelayServer::bind("127.0.0.1:0", None).unwrap();
+    let mut a = join(&mut server, "first");
+    type_text(&mut a, 0, "one");
+    settle(&mut server, "first", &mut [&mut a]);
+
+    a.join_room("second");
+    wait_joined(&mut server, &mut a);
+    assert_eq!(text(&a.doc), "");
+    type_text(&mut a, 0, "two");
+    settle(&mut server, "second", &mut [&mut a]);
+    assert_eq!(server.doc("first").unwrap().to_string(), "one");
+    assert_eq!(server.doc("second").unwrap().to_string(), "two");
+}
+
+#[test]
+fn test_client_switches_rooms_before_welcome() {
+    let mut server = RelayServer::bind("127.0.0.1:0", None).unwrap();
+    let mut b = join(&mut server, "first");
+    type_text(&mut b, 0, "old");
+    settle(&mut server, "first", &mut [&mut b]);
+
+    // Both joins reach the server before any Welcome comes back
+    let mut a = RelayClient::connect(server.local_addr(), "first").unwrap();
+    a.join_room("second");
+    wait_joined(&mut server, &mut a);
+    assert_eq!(text(&a.doc), "");
+    type_text(&mut a, 0, "new");
+    settle(&mut server, "second", &mut [&mut a]);
+    assert_eq!(server.doc("first").unwrap().to_string(), "old");
+    assert_eq!(server.doc("second").unwrap().to_string(), "new");
+
+    // Edits in the first room no longer reach it
+    type_text(&mut b, 3, "!");
+    settle(&mut server, "first", &mut [&mut b]);
+    a.poll().unwrap();
+    assert_eq!(text(&a.doc), "new");
+}
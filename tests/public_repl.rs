@@ -0,0 +1,104 @@
+use simple_text_crdt::repl::Repl;
+
+fn run(repl: &mut Repl, line: &str) -> String {
+    let mut out = Vec::new();
+    repl.execute(line, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_edit_commands() {
+    let mut repl = Repl::new();
+    run(&mut repl, "insert 0 hello");
+    run(&mut repl, "insert 0 oh, ");
+    run(&mut repl, "delete 2 2");
+    assert_eq!(run(&mut repl, "show"), "ohhello\n");
+
+    let ids = run(&mut repl, "ids");
+    assert_eq!(ids.lines().count(), 7);
+    assert!(ids.lines().next().unwrap().starts_with("0\t'o'\t"));
+    assert!(run(&mut repl, "tree").starts_with("major \n"));
+    // One signal per command
+    assert_eq!(repl.log.len(), 3);
+
+    // Deleting past the end deletes nothing
+    assert!(repl.execute("delete 5 5", &mut Vec::new()).is_err());
+    assert_eq!(run(&mut repl, "show"), "ohhello\n");
+    assert_eq!(repl.log.len(), 3);
+
+    assert!(repl.execute("insert x y", &mut Vec::new()).is_err());
+    assert!(repl.execute("frobnicate", &mut Vec::new()).is_err());
+    assert!(!repl.execute("quit", &mut Vec::new()).unwrap());
+}
+
+#[test]
+fn test_save_load_and_replay() {
+    let dir = std::env::temp_dir().join(format!("treedoc-repl-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let doc = dir.join("doc.bin");
+    let script = dir.join("bug.txt");
+    std::fs::write(
+        &script,
+        "# typed by a user\ninsert 0 abc\n\ninsert 3 \\nd\ndelete 0\n",
+    )
+    .unwrap();
+
+    let mut repl = Repl::new();
+    let out = run(&mut repl, &format!("replay {}", script.display()));
+    assert!(out.starts_with("> insert 0 abc\n"));
+    assert_eq!(repl.doc.to_string(), "bc\nd");
+    run(&mut repl, &format!("save {}", doc.display()));
+
+    let mut other = Repl::new();
+    run(&mut other, "insert 0 zzz");
+    run(&mut other, &format!("load {}", doc.display()));
+    assert_eq!(run(&mut other, "show"), "bc\nd\n");
+    assert_eq!(run(&mut other, "ids"), run(&mut repl, "ids"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_replay_does_not_nest() {
+    let dir = std::env::temp_dir().join(format!("treedoc-repl-nest-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("loop.txt");
+    std::fs::write(
+        &script,
+        format!("insert 0 a\nreplay {}\n", script.display()),
+    )
+    .unwrap();
+
+    let mut repl = Repl::new();
+    let out = run(&mut repl, &format!("replay {}", script.display()));
+    assert!(out.ends_with("error: replay can't be used in a replayed file\n"));
+    assert_eq!(repl.doc.to_string(), "a");
+    // Replaying works again once the script is done
+    run(&mut repl, &format!("replay {}", script.display()));
+    assert_eq!(repl.doc.to_string(), "aa");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_command_line() {
+    let bin = env!("CARGO_BIN_EXE_simple-text-crdt");
+    // Without -c a `;` is just text
+    let status = std::process::Command::new(bin)
+        .args(["insert", "0", "a;b"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let out = std::process::Command::new(bin)
+        .args(["-c", "insert 0 a;b; show"])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let out = std::process::Command::new(bin)
+        .args(["-c", "insert 0 ab; delete 0 1; show"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "b\n");
+}
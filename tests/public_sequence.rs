@@ -0,0 +1,220 @@
+use std::io::ErrorKind;
+
+use simple_text_crdt::{
+    logoot::Logoot, node::Atom, rga::Rga, sequence::SequenceCrdt, treedoc::Treedoc,
+};
+
+mod common;
+use common::Rng;
+
+// Conformance suite every `SequenceCrdt` backend has to pass
+
+fn edit<S: SequenceCrdt>(s: &mut S, insert: Option<(usize, char)>, delete: Option<usize>) -> S::Op {
+    let op = match (insert, delete) {
+        (Some((i, ch)), _) => s.insert(i, Atom::Char(ch)).unwrap(),
+        (_, Some(i)) => s.delete(i).unwrap(),
+        _ => unreachable!(),
+    };
+    s.apply(op.clone()).unwrap();
+    op
+}
+
+fn type_text<S: SequenceCrdt>(s: &mut S, index: usize, text: &str) -> Vec<S::Op> {
+    text.chars()
+        .enumerate()
+        .map(|(i, ch)| edit(s, Some((index + i, ch)), None))
+        .collect()
+}
+
+fn apply_all<S: SequenceCrdt>(s: &mut S, ops: &[S::Op]) {
+    for op in ops {
+        s.apply(op.clone()).unwrap();
+    }
+}
+
+// Random edits on one replica match the same edits on a Vec
+fn sequential_edits_match_a_vec<S: SequenceCrdt>() {
+    let mut rng = Rng(0x2545f4914f6cdd1d);
+    let mut s = S::with_site(1);
+    let mut model = Vec::new();
+    let mut ops = Vec::new();
+    for _ in 0..500 {
+        if model.is_empty() || rng.below(3) > 0 {
+            let i = rng.below(model.len() + 1);
+            let ch = (b'a' + rng.below(26) as u8) as char;
+            ops.push(edit(&mut s, Some((i, ch)), None));
+            model.insert(i, ch);
+        } else {
+            let i = rng.below(model.len());
+            ops.push(edit(&mut s, None, Some(i)));
+            model.remove(i);
+        }
+        assert_eq!(s.len(), model.len());
+    }
+    assert_eq!(s.text(), model.iter().collect::<String>());
+
+    let mut other = S::with_site(2);
+    apply_all(&mut other, &ops);
+    assert_eq!(other.text(), s.text());
+}
+
+fn out_of_range_is_rejected<S: SequenceCrdt>() {
+    let mut s = S::with_site(1);
+    assert!(s.is_empty());
+    assert_eq!(s.delete(0).unwrap_err().kind(), ErrorKind::InvalidInput);
+    type_text(&mut s, 0, "ab");
+    assert_eq!(
+        s.insert(3, Atom::Char('x')).unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+    assert_eq!(s.delete(2).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(s.text(), "ab");
+}
+
+// Concurrent inserts into the same gap both land in it, in the same order
+fn concurrent_inserts_stay_in_their_gap<S: SequenceCrdt>() {
+    let mut a = S::with_site(1);
+    let mut b = S::with_site(2);
+    let base = type_text(&mut a, 0, "ab");
+    apply_all(&mut b, &base);
+
+    let from_a = type_text(&mut a, 1, "xy");
+    let from_b = type_text(&mut b, 1, "z");
+    apply_all(&mut a, &from_b);
+    apply_all(&mut b, &from_a);
+
+    let text = a.text();
+    assert_eq!(text, b.text());
+    assert!(text.starts_with('a') && text.ends_with('b'), "{text}");
+    assert!(text.find('x') < text.find('y'), "{text}");
+    assert_eq!(text.len(), 5);
+}
+
+fn concurrent_delete_and_insert<S: SequenceCrdt>() {
+    let mut a = S::with_site(1);
+    let mut b = S::with_site(2);
+    let base = type_text(&mut a, 0, "abc");
+    apply_all(&mut b, &base);
+
+    // Both delete 'b', b also types next to it
+    let del_a = edit(&mut a, None, Some(1));
+    let del_b = edit(&mut b, None, Some(1));
+    let ins_b = type_text(&mut b, 1, "!");
+    a.apply(del_b).unwrap();
+    apply_all(&mut a, &ins_b);
+    b.apply(del_a).unwrap();
+    assert_eq!(a.text(), "a!c");
+    assert_eq!(b.text(), "a!c");
+    assert_eq!(a.len(), 3);
+}
+
+// Ops made before any of them is applied all take effect
+fn ops_made_before_applying<S: SequenceCrdt>() {
+    let mut a = S::with_site(1);
+    let mut b = S::with_site(2);
+    let base = type_text(&mut a, 0, "ab");
+    apply_all(&mut b, &base);
+
+    let ops = [
+        a.insert(0, Atom::Char('x')).unwrap(),
+        a.insert(2, Atom::Char('y')).unwrap(),
+    ];
+    apply_all(&mut a, &ops);
+    apply_all(&mut b, &ops);
+    assert_eq!(a.text(), "xaby");
+    assert_eq!(b.text(), "xaby");
+}
+
+// Three replicas edit at random and exchange ops in random causal order
+fn random_concurrent_editing_converges<S: SequenceCrdt>() {
+    for seed in 1..20u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x9e3779b97f4a7c15));
+        let mut replicas: Vec<S> = (1..=3).map(S::with_site).collect();
+        // (origin, deps) of every op, deps being what the origin had applied
+        let mut ops: Vec<(usize, Vec<usize>, S::Op)> = Vec::new();
+        let mut applied: Vec<Vec<bool>> = vec![Vec::new(); 3];
+
+        for _ in 0..300 {
+            let r = rng.below(3);
+            if rng.below(2) == 0 {
+                let s = &mut replicas[r];
+                let op = if s.is_empty() || rng.below(3) > 0 {
+                    let i = rng.below(s.len() + 1);
+                    edit(s, Some((i, (b'a' + rng.below(26) as u8) as char)), None)
+                } else {
+                    let i = rng.below(s.len());
+                    edit(s, None, Some(i))
+                };
+                let deps = (0..ops.len()).filter(|&i| applied[r][i]).collect();
+                ops.push((r, deps, op));
+                for seen in applied.iter_mut() {
+                    seen.push(false);
+                }
+                applied[r][ops.len() - 1] = true;
+            } else {
+                // Deliver one op whose dependencies are in
+                let ready: Vec<usize> = (0..ops.len())
+                    .filter(|&i| !applied[r][i] && ops[i].1.iter().all(|&d| applied[r][d]))
+                    .collect();
+                if !ready.is_empty() {
+                    let i = ready[rng.below(ready.len())];
+                    replicas[r].apply(ops[i].2.clone()).unwrap();
+                    applied[r][i] = true;
+                }
+            }
+        }
+        // Flush in generation order, which is causal
+        for (r, replica) in replicas.iter_mut().enumerate() {
+            for (i, (_, _, op)) in ops.iter().enumerate() {
+                if !applied[r][i] {
+                    replica.apply(op.clone()).unwrap();
+                }
+            }
+        }
+        let text = replicas[0].text();
+        assert!(replicas.iter().all(|s| s.text() == text), "seed {seed}");
+        assert_eq!(replicas[0].len(), text.chars().count());
+    }
+}
+
+macro_rules! conformance {
+    ($name:ident, $backend:ty) => {
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn sequential_edits_match_a_vec() {
+                super::sequential_edits_match_a_vec::<$backend>();
+            }
+
+            #[test]
+            fn out_of_range_is_rejected() {
+                super::out_of_range_is_rejected::<$backend>();
+            }
+
+            #[test]
+            fn concurrent_inserts_stay_in_their_gap() {
+                super::concurrent_inserts_stay_in_their_gap::<$backend>();
+            }
+
+            #[test]
+            fn concurrent_delete_and_insert() {
+                super::concurrent_delete_and_insert::<$backend>();
+            }
+
+            #[test]
+            fn ops_made_before_applying() {
+                super::ops_made_before_applying::<$backend>();
+            }
+
+            #[test]
+            fn random_concurrent_editing_converges() {
+                super::random_concurrent_editing_converges::<$backend>();
+            }
+        }
+    };
+}
+
+conformance!(treedoc, Treedoc);
+conformance!(rga, Rga);
+conformance!(logoot, Logoot);
@@ -0,0 +1,136 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use simple_text_crdt::{
+    encoding::to_bytes,
+    oplog::{OpId, VersionVector},
+    sync::{Message, Peer},
+    treedoc::Signal,
+};
+
+mod common;
+use common::text;
+
+fn type_text(peer: &mut Peer, pos: usize, text: &str) {
+    for (i, ch) in text.chars().enumerate() {
+        let sig = peer.doc.insert(pos + i, ch).unwrap();
+        peer.apply_local(Signal::Insert(sig)).unwrap();
+    }
+}
+
+// Polls every peer until all of them have seen the same ops
+fn settle(peers: &mut [&mut Peer]) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        for peer in peers.iter_mut() {
+            peer.poll_timeout(Duration::from_millis(5)).unwrap();
+        }
+        let version = peers[0].log.version().clone();
+        if peers.iter().all(|p| *p.log.version() == version) {
+            return;
+        }
+        assert!(Instant::now() < deadline, "peers did not converge");
+    }
+}
+
+#[test]
+fn test_three_peers_converge() {
+    let mut a = Peer::new(1);
+    let mut b = Peer::new(2);
+    let mut c = Peer::new(3);
+
+    // Edits made before connecting are sent in the handshake
+    type_text(&mut a, 0, "hello");
+    let addr = a.listen("127.0.0.1:0").unwrap();
+    b.connect(addr).unwrap();
+    settle(&mut [&mut a, &mut b]);
+    assert_eq!(text(&b.doc), "hello");
+
+    type_text(&mut c, 0, "!?");
+    c.connect(addr).unwrap();
+    settle(&mut [&mut a, &mut b, &mut c]);
+    let merged = text(&a.doc);
+    assert_eq!(merged.len(), 7);
+    assert_eq!(text(&b.doc), merged);
+    assert_eq!(text(&c.doc), merged);
+
+    // Concurrent edits at the same spot, b and c only hear of each other through a
+    type_text(&mut b, 0, "xy");
+    type_text(&mut c, 0, "zw");
+    let sig = a.doc.delete(0).unwrap();
+    a.apply_local(Signal::Delete(sig)).unwrap();
+    settle(&mut [&mut a, &mut b, &mut c]);
+
+    let merged = text(&a.doc);
+    assert_eq!(merged.chars().count(), 10);
+    assert_eq!(text(&b.doc), merged);
+    assert_eq!(text(&c.doc), merged);
+    assert_eq!(a.connections(), 2);
+}
+
+#[test]
+fn test_duplicate_site_is_rejected() {
+    let mut a = Peer::new(7);
+    let mut b = Peer::new(7);
+    let addr = a.listen("127.0.0.1:0").unwrap();
+    b.connect(addr).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while a.connections() + b.connections() > 0 {
+        a.poll_timeout(Duration::from_millis(5)).unwrap();
+        b.poll_timeout(Duration::from_millis(5)).unwrap();
+        assert!(Instant::now() < deadline, "connection was not closed");
+    }
+}
+
+#[test]
+fn test_bad_op_closes_only_its_connection() {
+    let mut a = Peer::new(1);
+    let mut b = Peer::new(2);
+    let addr = a.listen("127.0.0.1:0").unwrap();
+    b.connect(addr).unwrap();
+    let sig = a.doc.insert(0, 'h').unwrap();
+    a.apply_local(Signal::Insert(sig.clone())).unwrap();
+    settle(&mut [&mut a, &mut b]);
+
+    // A third peer claims a's insert as its own op, which a can't apply twice
+    let mut bad = TcpStream::connect(addr).unwrap();
+    let ops = Message::Ops(vec![(OpId { site: 9, seq: 1 }, Signal::Insert(sig))]);
+    for msg in [
+        Message::Hello {
+            site: 9,
+            version: VersionVector::new(),
+        },
+        ops,
+    ] {
+        let payload = to_bytes(&msg);
+        bad.write_all(&(payload.len() as u32).to_be_bytes())
+            .unwrap();
+        bad.write_all(&payload).unwrap();
+    }
+    // a hangs up on it
+    bad.set_read_timeout(Some(Duration::from_millis(5)))
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut buf = [0; 1024];
+    loop {
+        a.poll_timeout(Duration::from_millis(5)).unwrap();
+        match bad.read(&mut buf) {
+            Ok(0) => break,
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => break,
+            _ => assert!(Instant::now() < deadline, "bad peer was not closed"),
+        }
+    }
+    assert_eq!(a.connections(), 1);
+    assert_eq!(a.log.version().get(9), 0);
+
+    // Syncing with the others goes on
+    type_text(&mut b, 1, "i!");
+    type_text(&mut a, 0, ">");
+    settle(&mut [&mut a, &mut b]);
+    assert_eq!(text(&a.doc), ">hi!");
+    assert_eq!(text(&b.doc), text(&a.doc));
+}
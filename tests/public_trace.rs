@@ -0,0 +1,97 @@
+use std::io::ErrorKind;
+
+use simple_text_crdt::{
+    logoot::Logoot,
+    rga::Rga,
+    sequence::SequenceCrdt,
+    trace::{Patch, Trace},
+    treedoc::Treedoc,
+};
+
+fn replay_fixture<S: SequenceCrdt>() {
+    let trace = Trace::load("tests/fixtures/letter.json").unwrap();
+    let mut doc = S::with_site(1);
+    let ops = trace.replay(&mut doc).unwrap();
+    assert_eq!(Some(doc.text()), trace.end);
+
+    let mut remote = S::with_site(2);
+    for op in ops {
+        remote.apply(op).unwrap();
+    }
+    assert_eq!(remote.text(), doc.text());
+}
+
+#[test]
+fn test_replay_fixture() {
+    replay_fixture::<Treedoc>();
+    replay_fixture::<Rga>();
+    replay_fixture::<Logoot>();
+}
+
+#[test]
+fn test_layouts() {
+    let patches = vec![
+        Patch {
+            pos: 0,
+            del: 0,
+            text: "hi".to_string(),
+        },
+        Patch {
+            pos: 1,
+            del: 1,
+            text: "\u{1F600}".to_string(),
+        },
+    ];
+    let bare = Trace::parse(r#"[[0, 0, "hi"], [1, 1, "😀"]]"#).unwrap();
+    assert_eq!(bare.patches, patches);
+    assert_eq!(bare.end, None);
+
+    let automerge_perf = Trace::parse(
+        r#"{"edits": [[0, 0, "hi"], [1, 1, "😀"]], "finalText": "h😀", "other": [null, true]}"#,
+    )
+    .unwrap();
+    assert_eq!(automerge_perf.patches, patches);
+    assert_eq!(automerge_perf.end.as_deref(), Some("h😀"));
+
+    let editing_traces = Trace::parse(
+        r#"{
+            "startContent": "> ",
+            "endContent": "> h😀",
+            "txns": [
+                {"time": "2021-01-01T00:00:00Z", "patches": [[2, 0, "hi"]]},
+                {"time": "2021-01-01T00:00:01Z", "patches": [[3, 1, "😀"]]}
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(editing_traces.start, "> ");
+    let mut td = Treedoc::default();
+    editing_traces.replay(&mut td).unwrap();
+    assert_eq!(td.to_string(), "> h😀");
+}
+
+#[test]
+fn test_bad_traces() {
+    for json in [
+        "",
+        "[[0, 0, \"a\"]",
+        "[[0, -1]]",
+        "[[0.5, 0]]",
+        "[[0, 0, 1]]",
+        "{\"edits\": [], \"txns\": []}",
+        "{\"finalText\": \"a\"}",
+        "[] []",
+    ] {
+        let err = Trace::parse(json).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{json}");
+    }
+
+    // Replaying checks the text the trace says it ends in
+    let trace = Trace::parse(r#"{"edits": [[0, 0, "ab"]], "finalText": "ba"}"#).unwrap();
+    let err = trace.replay(&mut Treedoc::default()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let trace = Trace::parse("[[1, 0, \"a\"]]").unwrap();
+    let err = trace.replay(&mut Treedoc::default()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
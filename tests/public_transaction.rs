@@ -0,0 +1,124 @@
+use std::io::ErrorKind;
+
+use simple_text_crdt::{
+    encoding::{from_bytes, to_bytes},
+    events::Change,
+    marks::Mark,
+    offset::CharOffset,
+    treedoc::{Signal, Treedoc},
+};
+
+mod common;
+use common::record;
+
+fn type_text(td: &mut Treedoc, pos: usize, text: &str) -> Vec<Signal> {
+    let mut sigs = Vec::new();
+    for (i, ch) in text.chars().enumerate() {
+        let sig = Signal::Insert(td.insert(CharOffset(pos + i), ch).unwrap());
+        td.apply(sig.clone()).unwrap();
+        sigs.push(sig);
+    }
+    sigs
+}
+
+#[test]
+fn test_replace_word() {
+    let mut a = Treedoc::default();
+    let base = type_text(&mut a, 0, "hello world");
+    let mut b = Treedoc {
+        unique_disambiguator: 2,
+        ..Treedoc::default()
+    };
+    for sig in base {
+        b.apply(sig).unwrap();
+    }
+    let changes = record(&mut b);
+
+    let tx = a
+        .transaction(|tx| {
+            tx.delete_range(6..11)?;
+            tx.insert_str(6, "there")?;
+            tx.mark(6..11, Mark::Bold)
+        })
+        .unwrap();
+    // Building it leaves the document alone, like any other signal
+    assert_eq!(a.to_string(), "hello world");
+    a.apply(tx.clone()).unwrap();
+    assert_eq!(a.to_string(), "hello there");
+
+    b.apply(from_bytes(&to_bytes(&tx)).unwrap()).unwrap();
+    assert_eq!(b.to_string(), "hello there");
+    assert_eq!(b.root_hash(), a.root_hash());
+    assert_eq!(b.formatted_runs(0..11), a.formatted_runs(0..11));
+
+    let changes = changes.borrow();
+    assert_eq!(changes.len(), 1);
+    let Change::Transaction(inner) = &changes[0] else {
+        panic!("expected one transaction change");
+    };
+    assert_eq!(inner.len(), 11);
+    assert_eq!(inner[0], Change::Deleted { index: 6, len: 1 });
+    assert_eq!(inner[10], Change::Formatted { range: 6..11 });
+}
+
+#[test]
+fn test_remote_apply_is_all_or_nothing() {
+    let mut a = Treedoc::default();
+    let base = type_text(&mut a, 0, "ab");
+    let mut b = Treedoc {
+        unique_disambiguator: 2,
+        ..Treedoc::default()
+    };
+    for sig in base {
+        b.apply(sig).unwrap();
+    }
+    let changes = record(&mut b);
+
+    // b has not seen the 'x' the second half of the transaction hangs off
+    let x = type_text(&mut a, 2, "x");
+    let tx = a
+        .transaction(|tx| {
+            tx.insert(0, '>')?;
+            tx.insert(4, '!')
+        })
+        .unwrap();
+    a.apply(tx.clone()).unwrap();
+
+    let hash = b.root_hash();
+    let err = b.apply(tx.clone()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert_eq!(b.to_string(), "ab");
+    assert_eq!(b.doc_length, 2);
+    assert_eq!(b.root_hash(), hash);
+    assert!(changes.borrow().is_empty());
+
+    b.apply(x[0].clone()).unwrap();
+    b.apply(tx).unwrap();
+    assert_eq!(b.to_string(), ">abx!");
+    assert_eq!(b.root_hash(), a.root_hash());
+}
+
+#[test]
+fn test_failed_edit_leaves_doc_alone() {
+    let mut td = Treedoc::default();
+    type_text(&mut td, 0, "abc");
+    let hash = td.root_hash();
+    let err = td
+        .transaction(|tx| {
+            tx.insert_str(0, "xyz")?;
+            assert_eq!(tx.doc().to_string(), "xyzabc");
+            tx.delete(CharOffset(10))
+        })
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert_eq!(td.to_string(), "abc");
+    assert_eq!(td.root_hash(), hash);
+}
+
+#[test]
+fn test_transactions_do_not_nest() {
+    let nested = Signal::Transaction(vec![Signal::Transaction(Vec::new())]);
+    assert!(from_bytes::<Signal>(&to_bytes(&nested)).is_err());
+    let mut td = Treedoc::default();
+    assert_eq!(td.apply(nested).unwrap_err().kind(), ErrorKind::InvalidData);
+}
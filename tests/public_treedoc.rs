@@ -1,4 +1,9 @@
-use simple_text_crdt::treedoc::{Signal, Treedoc};
+use simple_text_crdt::{
+    node::Atom,
+    offset::CharOffset,
+    pos_id::PosID,
+    treedoc::{Signal, Treedoc},
+};
 
 #[test]
 fn test_insert_start() {
@@ -70,7 +75,7 @@ fn test_delete() {
     let res = td.apply(Signal::Insert(sig.unwrap()));
     assert!(res.is_ok());
 
-    let sig = td.delete(td.doc_length);
+    let sig = td.delete(td.doc_length - 1);
     let res = td.apply(Signal::Delete(sig.unwrap()));
     assert!(res.is_ok());
 
@@ -88,3 +93,172 @@ fn test_delete() {
     let res_string: String = nodes.iter().collect();
     assert_eq!(res_string, "b");
 }
+
+#[test]
+fn test_read_api() {
+    let mut td = Treedoc::new('a');
+    for ch in "bcdef".chars() {
+        let sig = td.insert(td.doc_length, ch);
+        td.apply(Signal::Insert(sig.unwrap())).unwrap();
+    }
+    let sig = td.delete(2);
+    td.apply(Signal::Delete(sig.unwrap())).unwrap();
+
+    assert_eq!(td.to_string(), "abdef");
+    assert_eq!(td.slice(1..3), "bd");
+    assert_eq!(td.slice(3..), "ef");
+    assert_eq!(td.slice(..10), "abdef");
+    assert_eq!(td.char_at(2), Some(Atom::Char('d')));
+    assert_eq!(td.char_at(5), None);
+    assert_eq!(td.atoms().skip(4).collect::<String>(), "f");
+
+    let ids: Vec<_> = td.iter_with_ids().collect();
+    assert_eq!(ids.len(), td.doc_length);
+    assert_eq!(ids[2].1, Atom::Char('d'));
+    assert_eq!(td.iter_with_ids().nth(2), Some(ids[2].clone()));
+}
+
+#[test]
+fn test_pos_id_lookup() {
+    let mut td = Treedoc::new('a');
+    for ch in "bcd".chars() {
+        let sig = td.insert(td.doc_length, ch);
+        td.apply(Signal::Insert(sig.unwrap())).unwrap();
+    }
+
+    // Anchor something to 'c' and keep finding it while the text around it moves
+    let c_id = td.iter().nth(2).unwrap();
+    assert_eq!(td.atom_at_id(&c_id), Some(Atom::Char('c')));
+
+    let sig = td.delete(0);
+    td.apply(Signal::Delete(sig.unwrap())).unwrap();
+    assert_eq!(td.pos_of(&c_id), Some(1));
+
+    let sig = td.delete(1);
+    td.apply(Signal::Delete(sig.unwrap())).unwrap();
+    assert_eq!(td.pos_of(&c_id), None);
+    assert_eq!(td.atom_at_id(&c_id), None);
+
+    for (idx, (pos_id, atom)) in td.iter_with_ids().enumerate() {
+        assert_eq!(td.pos_of(&pos_id), Some(idx));
+        assert_eq!(td.atom_at_id(&pos_id), Some(atom));
+    }
+}
+
+#[test]
+fn test_insert_between_prepended() {
+    let mut td = Treedoc::default();
+    for (pos, ch) in [(0, 'e'), (0, 'a'), (1, 'c'), (1, 'b'), (3, 'd')] {
+        let sig = td.insert(pos, ch);
+        td.apply(Signal::Insert(sig.unwrap())).unwrap();
+    }
+    assert_eq!(td.to_string(), "abcde");
+}
+
+#[test]
+fn test_snapshot_rebuilds_tree() {
+    let mut a = Treedoc::default();
+    let mut b = Treedoc {
+        unique_disambiguator: 2,
+        ..Treedoc::default()
+    };
+    // Concurrent inserts into the same slot end up as minisiblings
+    let sig_a = a.insert(0, 'x').unwrap();
+    let sig_b = b.insert(0, 'y').unwrap();
+    for td in [&mut a, &mut b] {
+        td.apply(Signal::Insert(sig_a.clone())).unwrap();
+        td.apply(Signal::Insert(sig_b.clone())).unwrap();
+    }
+    for (pos, ch) in [(0, 'a'), (3, 'b'), (2, 'c'), (1, 'd')] {
+        let sig = a.insert(pos, ch).unwrap();
+        a.apply(Signal::Insert(sig)).unwrap();
+    }
+    let sig = a.delete(1).unwrap();
+    a.apply(Signal::Delete(sig)).unwrap();
+
+    let mut rebuilt = Treedoc::default();
+    for sig in a.snapshot() {
+        rebuilt.apply(sig).unwrap();
+    }
+    assert_eq!(rebuilt.to_string(), a.to_string());
+    assert_eq!(rebuilt.to_dot(), a.to_dot());
+    assert_eq!(rebuilt.root_hash(), a.root_hash());
+}
+
+#[test]
+fn test_root_hash_and_diff() {
+    let mut a = Treedoc::default();
+    let mut b = Treedoc {
+        unique_disambiguator: 2,
+        ..Treedoc::default()
+    };
+    let mut ops = Vec::new();
+    for (pos, ch) in [(0, 'a'), (1, 'b'), (1, 'c'), (0, 'd')] {
+        let sig = a.insert(pos, ch).unwrap();
+        a.apply(Signal::Insert(sig.clone())).unwrap();
+        ops.push(sig);
+    }
+    let sig_b = b.insert(0, 'x').unwrap();
+    b.apply(Signal::Insert(sig_b.clone())).unwrap();
+    // Same ops in another order give the same tree
+    a.apply(Signal::Insert(sig_b)).unwrap();
+    for sig in ops {
+        b.apply(Signal::Insert(sig)).unwrap();
+    }
+    assert_eq!(a.root_hash(), b.root_hash());
+    assert!(a.diff(&b).is_empty());
+
+    // A delete on one side, an insert on the other
+    let del = a.delete(CharOffset(2)).unwrap();
+    a.apply(Signal::Delete(del)).unwrap();
+    let ins = b.insert(5, 'y').unwrap();
+    b.apply(Signal::Insert(ins)).unwrap();
+    assert_ne!(a.root_hash(), b.root_hash());
+
+    let diff = a.diff(&b);
+    assert_eq!(diff.len(), 2);
+    assert!(
+        diff.iter()
+            .any(|p| a.atom_at_id(p).is_none() && b.atom_at_id(p).is_some())
+    );
+    // The new atom sits in a major node only b has
+    assert!(
+        diff.iter()
+            .any(|p| a.child_hashes(p).is_empty() && !b.child_hashes(p).is_empty())
+    );
+
+    // The same divergence found by comparing child hashes level by level
+    let mut frontier = vec![PosID::new()];
+    let mut differing = 0;
+    while let Some(path) = frontier.pop() {
+        let theirs = b.child_hashes(&path);
+        for (step, hash) in a.child_hashes(&path) {
+            if theirs.iter().all(|(s, h)| *s != step || *h != hash) {
+                differing += 1;
+                frontier.push(PosID([path.0.as_slice(), &[step]].concat()));
+            }
+        }
+    }
+    assert!(differing > 0);
+}
+
+#[test]
+fn test_stats() {
+    let mut td = Treedoc::default();
+    for (i, ch) in "abc".chars().enumerate() {
+        let sig = td.insert(i, ch).unwrap();
+        td.apply(Signal::Insert(sig)).unwrap();
+    }
+    let sig = td.delete(CharOffset(1)).unwrap();
+    td.apply(Signal::Delete(sig)).unwrap();
+
+    let stats = td.stats();
+    assert_eq!(stats.mininodes, 3);
+    assert_eq!(stats.tombstones, 1);
+    let lens: Vec<usize> = td.iter().map(|id| id.0.len()).collect();
+    assert_eq!(stats.depth, *lens.iter().max().unwrap());
+    assert_eq!(
+        stats.avg_pos_id_len,
+        lens.iter().sum::<usize>() as f64 / lens.len() as f64
+    );
+}
@@ -0,0 +1,187 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use simple_text_crdt::{
+    encoding::{from_bytes, to_bytes},
+    relay::{ClientMessage, RelayClient, RelayServer, ServerMessage},
+    treedoc::Signal,
+    websocket::accept_key,
+};
+
+mod common;
+use common::{TIMEOUT, settle, text, wait_joined};
+
+fn type_text(client: &mut RelayClient, pos: usize, text: &str) {
+    for (i, ch) in text.chars().enumerate() {
+        let sig = client.doc.insert(pos + i, ch).unwrap();
+        client.apply_local(Signal::Insert(sig)).unwrap();
+    }
+}
+
+// Masked client frame with the given first byte (FIN + opcode)
+fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+    assert!(payload.len() < 126);
+    let key = [1, 2, 3, 4];
+    let mut frame = vec![first, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&key);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+    frame
+}
+
+#[test]
+fn test_accept_key() {
+    // Example from RFC 6455
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[test]
+fn test_websocket_and_tcp_clients_share_a_room() {
+    let mut server = RelayServer::bind("127.0.0.1:0", None).unwrap();
+    let ws_addr = server.listen_ws("127.0.0.1:0").unwrap();
+
+    let mut tcp = RelayClient::connect(server.local_addr(), "doc").unwrap();
+    wait_joined(&mut server, &mut tcp);
+    type_text(&mut tcp, 0, "hello");
+    settle(&mut server, "doc", &mut [&mut tcp]);
+
+    let mut ws = RelayClient::connect_ws(ws_addr, "doc").unwrap();
+    wait_joined(&mut server, &mut ws);
+    assert_eq!(text(&ws.doc), "hello");
+
+    type_text(&mut ws, 5, " world");
+    type_text(&mut tcp, 0, ">");
+    settle(&mut server, "doc", &mut [&mut tcp, &mut ws]);
+    assert_eq!(text(&ws.doc), text(&tcp.doc));
+    assert_eq!(text(&ws.doc).len(), 12);
+
+    // Resync replaces the document with the room's state, which is the same here
+    ws.resync();
+    let before = text(&ws.doc);
+    ws.doc = Default::default();
+    let deadline = Instant::now() + TIMEOUT;
+    while text(&ws.doc) != before {
+        server.poll_timeout(Duration::from_millis(5)).unwrap();
+        ws.poll().unwrap();
+        assert!(Instant::now() < deadline, "resync timed out");
+    }
+
+    // Moving to another room over the same connection
+    ws.join_room("other");
+    assert!(!ws.is_joined());
+    wait_joined(&mut server, &mut ws);
+    assert_eq!(text(&ws.doc), "");
+    type_text(&mut tcp, 0, "!");
+    type_text(&mut ws, 0, "x");
+    settle(&mut server, "doc", &mut [&mut tcp]);
+    settle(&mut server, "other", &mut [&mut ws]);
+    assert_eq!(text(&ws.doc), "x");
+    assert_eq!(text(&tcp.doc).len(), 13);
+}
+
+#[test]
+fn test_raw_browser_handshake_fragments_and_ping() {
+    let mut server = RelayServer::bind("127.0.0.1:0", None).unwrap();
+    let ws_addr = server.listen_ws("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(ws_addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(5)))
+        .unwrap();
+
+    stream
+        .write_all(
+            b"GET /chat HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+              Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+              Sec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .unwrap();
+    // A join split into two fragments with a ping in between
+    let join = to_bytes(&ClientMessage::Join {
+        room: "doc".to_string(),
+    });
+    let (first, rest) = join.split_at(3);
+    stream.write_all(&client_frame(0x02, first)).unwrap();
+    stream.write_all(&client_frame(0x89, b"hi")).unwrap();
+    stream.write_all(&client_frame(0x80, rest)).unwrap();
+
+    let mut received = Vec::new();
+    let deadline = Instant::now() + TIMEOUT;
+    let expected_head = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                         Connection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+    let pong = [0x8A, 2, b'h', b'i'];
+    // The Welcome frame is FIN + binary, an unmasked length and the encoded message
+    let welcome_at = expected_head.len() + pong.len();
+    let complete = |r: &[u8]| {
+        r.len() > welcome_at + 1 && r.len() >= welcome_at + 2 + r[welcome_at + 1] as usize
+    };
+    while !complete(&received) {
+        server.poll_timeout(Duration::from_millis(5)).unwrap();
+        let mut buf = [0u8; 1024];
+        if let Ok(n) = stream.read(&mut buf) {
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert!(Instant::now() < deadline, "no answer");
+    }
+
+    let (head, rest) = received.split_at(expected_head.len());
+    assert_eq!(head, expected_head.as_bytes());
+    let (got_pong, welcome) = rest.split_at(pong.len());
+    assert_eq!(got_pong, pong);
+    assert_eq!(welcome[0], 0x82);
+    let message: ServerMessage = from_bytes(&welcome[2..]).unwrap();
+    assert!(matches!(message, ServerMessage::Welcome { site: 1, .. }));
+}
+
+#[test]
+fn test_bad_upgrade_is_refused() {
+    let mut server = RelayServer::bind("127.0.0.1:0", None).unwrap();
+    let ws_addr = server.listen_ws("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(ws_addr).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    server.poll_timeout(Duration::from_millis(5)).unwrap();
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).unwrap();
+    assert!(answer.starts_with("HTTP/1.1 400"));
+}
+
+// Whether the server has hung up, waiting at most `wait` for it
+fn hung_up(stream: &mut TcpStream, wait: Duration) -> bool {
+    stream.set_read_timeout(Some(wait)).unwrap();
+    match stream.read(&mut [0u8; 64]) {
+        Ok(n) => n == 0,
+        Err(e) => !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+    }
+}
+
+#[test]
+fn test_unfinished_upgrades_are_dropped() {
+    let mut server = RelayServer::bind("127.0.0.1:0", None).unwrap();
+    server.handshake_timeout = Duration::from_millis(300);
+    let ws_addr = server.listen_ws("127.0.0.1:0").unwrap();
+    let mut silent = TcpStream::connect(ws_addr).unwrap();
+    // Each byte comes well within the timeout, the whole request doesn't
+    let mut slow = TcpStream::connect(ws_addr).unwrap();
+    let request = b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\r\n";
+    let start = Instant::now();
+    let mut sent = 0;
+    loop {
+        server.poll_timeout(Duration::from_millis(5)).unwrap();
+        if slow.write_all(&request[sent..sent + 1]).is_err()
+            || hung_up(&mut slow, Duration::from_millis(50))
+        {
+            break;
+        }
+        sent += 1;
+        assert!(sent < request.len(), "slow request was read to the end");
+    }
+    assert!(start.elapsed() < Duration::from_secs(3));
+    assert!(hung_up(&mut silent, TIMEOUT));
+    assert!(start.elapsed() < Duration::from_secs(3));
+}